@@ -1,25 +1,463 @@
+use clap::{App, Arg, ArgMatches};
+use crate::claim;
+use crate::claim::OrphanPolicy;
+use crate::hub::DEFAULT_API_URL;
+use crate::local::CleanPolicy;
+use failure::Error;
+use std::env;
+use std::fs;
+use std::time::Duration;
 
-use clap::{App, Arg};
+const DEFAULT_WORKSPACE: &str = "/tmp/crane";
+const DEFAULT_GIT_URL: &str = "https://github.com";
+const ENTERPRISE_API_PATH: &str = "/api/v3";
+
+/// S3 refuses presigned URLs that are valid for longer than a week.
+const MAX_PRESIGN_EXPIRY_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Fail, Debug)]
+pub enum ArgsError {
+    #[fail(display = "No {} was given. Set it with {}, {} or '{}' in the config file.", name, flag, variable, name)]
+    MissingSetting {
+        name: &'static str,
+        flag: &'static str,
+        variable: &'static str,
+    },
+
+    #[fail(display = "Target {} has no {} and there is no top-level default for it.", index, name)]
+    MissingTargetSetting {
+        index: usize,
+        name: &'static str,
+    },
+
+    #[fail(display = "{} is not a valid value for {}", value, name)]
+    InvalidSetting {
+        name: &'static str,
+        value: String,
+    },
+}
 
 #[derive(Debug)]
 pub struct Args {
-    pub user: String,
-    pub token: String,
+    pub auth: AuthArgs,
+    pub targets: Vec<TargetArgs>,
+    pub store: StoreArgs,
+    pub http_port: Option<u16>,
+    pub public_url: Option<String>,
+    pub api_url: String,
+    pub git_url: String,
+    pub ssh_key_file: Option<String>,
+    pub workspace: String,
+    pub clean: CleanPolicy,
+    pub fetch_depth: Option<u32>,
+    pub webhook_port: Option<u16>,
+    pub webhook_secret: Option<String>,
+    pub all_commits: bool,
+    pub pull_requests: bool,
+    pub checks: bool,
+    pub retry_command: Option<String>,
+    pub timeout: Option<Duration>,
+    pub orphan_age: Duration,
+    pub orphans: OrphanPolicy,
+    pub jobs: usize,
+}
+
+/// A branch of a repository to watch, and how to build and report on its commits.
+#[derive(Debug)]
+pub struct TargetArgs {
     pub owner: String,
     pub repository: String,
     pub branch: String,
     pub context: String,
     pub script: String,
-    pub region: String,
-    pub bucket: String,
 }
 
-pub fn parse_args() -> Args {
+/// How the agent authenticates with GitHub, for both API calls and clones.
+#[derive(Debug)]
+pub enum AuthArgs {
+    /// A personal access token belonging to `user`.
+    Token { user: String, token: String },
+    /// A GitHub App installation, given the app's PEM-encoded private key.
+    App { app_id: u64, private_key: Vec<u8>, installation_id: u64 },
+}
+
+/// Where build logs are saved.
+#[derive(Debug)]
+pub enum StoreArgs {
+    S3 { region: String, bucket: String, presign_expiry: Option<Duration> },
+    LocalDirectory { path: String, base_url: Option<String> },
+}
+
+/// Every setting that can be supplied from the command line, the environment or a config file.
+/// Each source fills in a `Settings` and they are layered in that order of precedence.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    user: Option<String>,
+    token: Option<String>,
+    token_file: Option<String>,
+    app_id: Option<u64>,
+    app_key_file: Option<String>,
+    installation_id: Option<u64>,
+    owner: Option<String>,
+    repository: Option<String>,
+    branch: Option<String>,
+    context: Option<String>,
+    script: Option<String>,
+    region: Option<String>,
+    bucket: Option<String>,
+    presign_expiry: Option<u64>,
+    log_dir: Option<String>,
+    log_url: Option<String>,
+    http_port: Option<u16>,
+    public_url: Option<String>,
+    api_url: Option<String>,
+    git_url: Option<String>,
+    ssh_key_file: Option<String>,
+    workspace: Option<String>,
+    clean: Option<String>,
+    fetch_depth: Option<u32>,
+    keep: Option<Vec<String>>,
+    webhook_port: Option<u16>,
+    webhook_secret: Option<String>,
+    webhook_secret_file: Option<String>,
+    all_commits: Option<bool>,
+    pull_requests: Option<bool>,
+    checks: Option<bool>,
+    retry_command: Option<String>,
+    timeout: Option<u64>,
+    orphan_age: Option<u64>,
+    orphans: Option<String>,
+    jobs: Option<usize>,
+    targets: Option<Vec<TargetSettings>>,
+}
+
+/// A watch target in the config file. Anything it leaves out is taken from the top-level settings.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TargetSettings {
+    owner: Option<String>,
+    repository: Option<String>,
+    branch: Option<String>,
+    context: Option<String>,
+    script: Option<String>,
+}
+
+impl Settings {
+    fn from_matches(matches: &ArgMatches) -> Result<Self, Error> {
+        let value = |key: &str| matches.value_of(key).map(str::to_string);
+        let flag = |key: &str| if matches.is_present(key) { Some(true) } else { None };
+        Ok(Settings {
+            user: value("user"),
+            token: value("token"),
+            token_file: value("token-file"),
+            app_id: parse_setting("app-id", value("app-id"))?,
+            app_key_file: value("app-key-file"),
+            installation_id: parse_setting("installation-id", value("installation-id"))?,
+            owner: value("owner"),
+            repository: value("repo"),
+            branch: value("branch"),
+            context: value("context"),
+            script: value("script"),
+            region: value("region"),
+            bucket: value("bucket"),
+            presign_expiry: parse_setting("presign-expiry", value("presign-expiry"))?,
+            log_dir: value("log-dir"),
+            log_url: value("log-url"),
+            http_port: parse_setting("http-port", value("http-port"))?,
+            public_url: value("public-url"),
+            api_url: value("api-url"),
+            git_url: value("git-url"),
+            ssh_key_file: value("ssh-key-file"),
+            workspace: value("workspace"),
+            clean: value("clean"),
+            fetch_depth: parse_setting("fetch-depth", value("fetch-depth"))?,
+            keep: matches.values_of("keep").map(|values| values.map(str::to_string).collect()),
+            webhook_port: parse_setting("webhook-port", value("webhook-port"))?,
+            webhook_secret: None,
+            webhook_secret_file: value("webhook-secret-file"),
+            all_commits: flag("all-commits"),
+            pull_requests: flag("pull-requests"),
+            checks: flag("checks"),
+            retry_command: value("retry-command"),
+            timeout: parse_setting("timeout", value("timeout"))?,
+            orphan_age: parse_setting("orphan-age", value("orphan-age"))?,
+            orphans: value("orphans"),
+            jobs: parse_setting("jobs", value("jobs"))?,
+            targets: None,
+        })
+    }
+
+    fn from_env() -> Result<Self, Error> {
+        let value = |key: &str| env::var(key).ok();
+        Ok(Settings {
+            user: value("CRANE_USER"),
+            token: value("CRANE_TOKEN"),
+            token_file: value("CRANE_TOKEN_FILE"),
+            app_id: parse_setting("CRANE_APP_ID", value("CRANE_APP_ID"))?,
+            app_key_file: value("CRANE_APP_KEY_FILE"),
+            installation_id: parse_setting("CRANE_INSTALLATION_ID", value("CRANE_INSTALLATION_ID"))?,
+            owner: value("CRANE_OWNER"),
+            repository: value("CRANE_REPO"),
+            branch: value("CRANE_BRANCH"),
+            context: value("CRANE_CONTEXT"),
+            script: value("CRANE_SCRIPT"),
+            region: value("AWS_REGION"),
+            bucket: value("CRANE_BUCKET"),
+            presign_expiry: parse_setting("CRANE_PRESIGN_EXPIRY", value("CRANE_PRESIGN_EXPIRY"))?,
+            log_dir: value("CRANE_LOG_DIR"),
+            log_url: value("CRANE_LOG_URL"),
+            http_port: parse_setting("CRANE_HTTP_PORT", value("CRANE_HTTP_PORT"))?,
+            public_url: value("CRANE_PUBLIC_URL"),
+            api_url: value("CRANE_API_URL"),
+            git_url: value("CRANE_GIT_URL"),
+            ssh_key_file: value("CRANE_SSH_KEY_FILE"),
+            workspace: value("CRANE_WORKSPACE"),
+            clean: value("CRANE_CLEAN"),
+            fetch_depth: parse_setting("CRANE_FETCH_DEPTH", value("CRANE_FETCH_DEPTH"))?,
+            keep: value("CRANE_KEEP").map(|value| value.split(',').map(str::to_string).collect()),
+            webhook_port: parse_setting("CRANE_WEBHOOK_PORT", value("CRANE_WEBHOOK_PORT"))?,
+            webhook_secret: value("CRANE_WEBHOOK_SECRET"),
+            webhook_secret_file: value("CRANE_WEBHOOK_SECRET_FILE"),
+            all_commits: parse_setting("CRANE_ALL_COMMITS", value("CRANE_ALL_COMMITS"))?,
+            pull_requests: parse_setting("CRANE_PULL_REQUESTS", value("CRANE_PULL_REQUESTS"))?,
+            checks: parse_setting("CRANE_CHECKS", value("CRANE_CHECKS"))?,
+            retry_command: value("CRANE_RETRY_COMMAND"),
+            timeout: parse_setting("CRANE_TIMEOUT", value("CRANE_TIMEOUT"))?,
+            orphan_age: parse_setting("CRANE_ORPHAN_AGE", value("CRANE_ORPHAN_AGE"))?,
+            orphans: value("CRANE_ORPHANS"),
+            jobs: parse_setting("CRANE_JOBS", value("CRANE_JOBS"))?,
+            targets: None,
+        })
+    }
+
+    fn from_file(path: &str) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// Fills in any settings missing from `self` with those from `fallback`.
+    fn or(self, fallback: Settings) -> Settings {
+        let (token, token_file) = secret_or((self.token, self.token_file),
+                                            (fallback.token, fallback.token_file));
+        let (webhook_secret, webhook_secret_file) = secret_or(
+            (self.webhook_secret, self.webhook_secret_file),
+            (fallback.webhook_secret, fallback.webhook_secret_file));
+        Settings {
+            user: self.user.or(fallback.user),
+            token,
+            token_file,
+            app_id: self.app_id.or(fallback.app_id),
+            app_key_file: self.app_key_file.or(fallback.app_key_file),
+            installation_id: self.installation_id.or(fallback.installation_id),
+            owner: self.owner.or(fallback.owner),
+            repository: self.repository.or(fallback.repository),
+            branch: self.branch.or(fallback.branch),
+            context: self.context.or(fallback.context),
+            script: self.script.or(fallback.script),
+            region: self.region.or(fallback.region),
+            bucket: self.bucket.or(fallback.bucket),
+            presign_expiry: self.presign_expiry.or(fallback.presign_expiry),
+            log_dir: self.log_dir.or(fallback.log_dir),
+            log_url: self.log_url.or(fallback.log_url),
+            http_port: self.http_port.or(fallback.http_port),
+            public_url: self.public_url.or(fallback.public_url),
+            api_url: self.api_url.or(fallback.api_url),
+            git_url: self.git_url.or(fallback.git_url),
+            ssh_key_file: self.ssh_key_file.or(fallback.ssh_key_file),
+            workspace: self.workspace.or(fallback.workspace),
+            clean: self.clean.or(fallback.clean),
+            fetch_depth: self.fetch_depth.or(fallback.fetch_depth),
+            keep: self.keep.or(fallback.keep),
+            webhook_port: self.webhook_port.or(fallback.webhook_port),
+            webhook_secret,
+            webhook_secret_file,
+            all_commits: self.all_commits.or(fallback.all_commits),
+            pull_requests: self.pull_requests.or(fallback.pull_requests),
+            checks: self.checks.or(fallback.checks),
+            retry_command: self.retry_command.or(fallback.retry_command),
+            timeout: self.timeout.or(fallback.timeout),
+            orphan_age: self.orphan_age.or(fallback.orphan_age),
+            orphans: self.orphans.or(fallback.orphans),
+            jobs: self.jobs.or(fallback.jobs),
+            targets: self.targets.or(fallback.targets),
+        }
+    }
+
+    fn into_args(self) -> Result<Args, Error> {
+        let targets = match self.targets {
+            Some(ref targets) if !targets.is_empty() => targets.iter()
+                .enumerate()
+                .map(|(index, target)| Ok(TargetArgs {
+                    owner: required_for_target(&target.owner, &self.owner, index, "owner")?,
+                    repository: required_for_target(&target.repository, &self.repository, index, "repository")?,
+                    branch: required_for_target(&target.branch, &self.branch, index, "branch")?,
+                    context: required_for_target(&target.context, &self.context, index, "context")?,
+                    script: required_for_target(&target.script, &self.script, index, "script")?,
+                }))
+                .collect::<Result<Vec<_>, ArgsError>>()?,
+            _ => vec![TargetArgs {
+                owner: required(self.owner, "owner", "-o", "CRANE_OWNER")?,
+                repository: required(self.repository, "repository", "-r", "CRANE_REPO")?,
+                branch: required(self.branch, "branch", "-b", "CRANE_BRANCH")?,
+                context: required(self.context, "context", "-c", "CRANE_CONTEXT")?,
+                script: required(self.script, "script", "-e", "CRANE_SCRIPT")?,
+            }],
+        };
+        // Authenticating as an app takes precedence over a personal token.
+        let auth = match self.app_id {
+            Some(app_id) => AuthArgs::App {
+                app_id,
+                private_key: fs::read(required(self.app_key_file, "app_key_file", "--app-key-file", "CRANE_APP_KEY_FILE")?)?,
+                installation_id: required(self.installation_id, "installation_id", "--installation-id",
+                                          "CRANE_INSTALLATION_ID")?,
+            },
+            None => AuthArgs::Token {
+                user: required(self.user, "user", "-u", "CRANE_USER")?,
+                token: required(read_secret(self.token, self.token_file)?, "token", "-t, --token-file",
+                                "CRANE_TOKEN, CRANE_TOKEN_FILE")?,
+            },
+        };
+        let webhook_secret = read_secret(self.webhook_secret, self.webhook_secret_file)?;
+        if self.webhook_port.is_some() && webhook_secret.is_none() {
+            return Err(ArgsError::MissingSetting {
+                name: "webhook_secret",
+                flag: "--webhook-secret-file",
+                variable: "CRANE_WEBHOOK_SECRET",
+            }.into());
+        }
+        // A local log directory, when given, saves needing any AWS settings at all.
+        let store = match self.log_dir {
+            Some(path) => StoreArgs::LocalDirectory { path, base_url: self.log_url },
+            None => StoreArgs::S3 {
+                region: required(self.region, "region", "--region", "AWS_REGION")?,
+                bucket: required(self.bucket, "bucket", "--bucket (or --log-dir)", "CRANE_BUCKET")?,
+                presign_expiry: match self.presign_expiry {
+                    Some(seconds) if seconds == 0 || seconds > MAX_PRESIGN_EXPIRY_SECS =>
+                        return Err(ArgsError::InvalidSetting { name: "presign_expiry", value: seconds.to_string() }.into()),
+                    expiry => expiry.map(Duration::from_secs),
+                },
+            },
+        };
+        // Listing directories to keep implies cleaning everything else.
+        let keep: Vec<String> = self.keep.unwrap_or_default().into_iter()
+            .map(|path| path.trim().trim_matches('/').to_string())
+            .filter(|path| !path.is_empty())
+            .collect();
+        let clean = match (self.clean.as_ref().map(String::as_str), keep.is_empty()) {
+            (None, true) | (Some("none"), _) => CleanPolicy::None,
+            (Some("all"), true) => CleanPolicy::All,
+            (None, false) | (Some("all"), false) => CleanPolicy::Keep(keep),
+            (Some(other), _) => return Err(ArgsError::InvalidSetting { name: "clean", value: other.to_string() }.into()),
+        };
+        // GitHub Enterprise serves its API under /api/v3 on the same host that serves git.
+        let api_url = self.api_url
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| DEFAULT_API_URL.to_string());
+        let git_url = match self.git_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None if api_url == DEFAULT_API_URL => DEFAULT_GIT_URL.to_string(),
+            None => api_url.trim_end_matches(ENTERPRISE_API_PATH).to_string(),
+        };
+        Ok(Args {
+            auth,
+            targets,
+            store,
+            http_port: self.http_port,
+            public_url: self.public_url,
+            api_url,
+            git_url,
+            ssh_key_file: self.ssh_key_file,
+            workspace: self.workspace.unwrap_or_else(|| DEFAULT_WORKSPACE.to_string()),
+            clean,
+            fetch_depth: match self.fetch_depth {
+                Some(0) => return Err(ArgsError::InvalidSetting { name: "fetch_depth", value: "0".to_string() }.into()),
+                fetch_depth => fetch_depth,
+            },
+            webhook_port: self.webhook_port,
+            webhook_secret,
+            all_commits: self.all_commits.unwrap_or(false),
+            pull_requests: self.pull_requests.unwrap_or(false),
+            checks: self.checks.unwrap_or(false),
+            retry_command: self.retry_command.filter(|command| !command.trim().is_empty()),
+            timeout: match self.timeout {
+                Some(0) => return Err(ArgsError::InvalidSetting { name: "timeout", value: "0".to_string() }.into()),
+                timeout => timeout.map(Duration::from_secs),
+            },
+            // Live claims are refreshed every heartbeat, so must not be mistaken for orphans.
+            orphan_age: match self.orphan_age {
+                Some(seconds) if Duration::from_secs(seconds) <= 2 * claim::HEARTBEAT_PERIOD =>
+                    return Err(ArgsError::InvalidSetting { name: "orphan_age", value: seconds.to_string() }.into()),
+                Some(seconds) => Duration::from_secs(seconds),
+                None => claim::DEFAULT_ORPHAN_AGE,
+            },
+            orphans: match self.orphans.as_ref().map(String::as_str) {
+                None | Some("rebuild") => OrphanPolicy::Rebuild,
+                Some("error") => OrphanPolicy::MarkError,
+                Some(other) => return Err(ArgsError::InvalidSetting { name: "orphans", value: other.to_string() }.into()),
+            },
+            jobs: match self.jobs {
+                Some(0) => return Err(ArgsError::InvalidSetting { name: "jobs", value: "0".to_string() }.into()),
+                Some(jobs) => jobs,
+                None => 1,
+            },
+        })
+    }
+}
+
+/// A secret and a file containing it are alternatives, so they must come from the same source.
+fn secret_or(secret: (Option<String>, Option<String>),
+             fallback: (Option<String>, Option<String>)) -> (Option<String>, Option<String>) {
+    if secret.0.is_some() || secret.1.is_some() {
+        secret
+    } else {
+        fallback
+    }
+}
+
+fn read_secret(secret: Option<String>, secret_file: Option<String>) -> Result<Option<String>, Error> {
+    match (secret, secret_file) {
+        (Some(secret), _) => Ok(Some(secret)),
+        (None, Some(secret_file)) => Ok(Some(fs::read_to_string(secret_file)?.trim().to_string())),
+        (None, None) => Ok(None),
+    }
+}
+
+fn required<T>(value: Option<T>, name: &'static str, flag: &'static str,
+               variable: &'static str) -> Result<T, ArgsError> {
+    value.ok_or(ArgsError::MissingSetting { name, flag, variable })
+}
+
+fn required_for_target(value: &Option<String>, default: &Option<String>, index: usize,
+                       name: &'static str) -> Result<String, ArgsError> {
+    value.as_ref()
+        .or(default.as_ref())
+        .map(String::to_string)
+        .ok_or(ArgsError::MissingTargetSetting { index, name })
+}
+
+fn parse_setting<T>(name: &'static str, value: Option<String>) -> Result<Option<T>, ArgsError>
+    where T: std::str::FromStr {
+    match value {
+        Some(value) => value.parse()
+            .map(Some)
+            .map_err(|_| ArgsError::InvalidSetting { name, value }),
+        None => Ok(None),
+    }
+}
+
+pub fn parse_args() -> Result<Args, Error> {
+    let config_key = "config";
+    let config_arg = Arg::with_name(config_key)
+        .long(config_key)
+        .value_name("FILE")
+        .help("TOML file to read settings from. Command line flags, then CRANE_* environment variables, take precedence over it. Multiple repositories can be watched by listing them as [[targets]] in this file.")
+        .takes_value(true);
+
     let user_key = "user";
     let user_arg = Arg::with_name(user_key)
         .short("u")
         .value_name("GITHUB_USERNAME")
-        .required(true)
         .help("User to connect to GitHub as.")
         .takes_value(true);
 
@@ -27,15 +465,42 @@ pub fn parse_args() -> Args {
     let token_arg = Arg::with_name(token_key)
         .short("t")
         .value_name("TOKEN")
-        .required(true)
-        .help("Authentication token to connect to GitHub.")
+        .help("Authentication token to connect to GitHub. Prefer --token-file or CRANE_TOKEN, which keep it out of the process list.")
+        .takes_value(true);
+
+    let token_file_key = "token-file";
+    let token_file_arg = Arg::with_name(token_file_key)
+        .long(token_file_key)
+        .value_name("FILE")
+        .help("File containing the authentication token to connect to GitHub.")
+        .conflicts_with(token_key)
+        .takes_value(true);
+
+    let app_id_key = "app-id";
+    let app_id_arg = Arg::with_name(app_id_key)
+        .long(app_id_key)
+        .value_name("ID")
+        .help("Authenticate as this GitHub App instead of with a personal token. Requires --app-key-file and --installation-id.")
+        .takes_value(true);
+
+    let app_key_file_key = "app-key-file";
+    let app_key_file_arg = Arg::with_name(app_key_file_key)
+        .long(app_key_file_key)
+        .value_name("FILE")
+        .help("PEM file containing the GitHub App's private key.")
+        .takes_value(true);
+
+    let installation_id_key = "installation-id";
+    let installation_id_arg = Arg::with_name(installation_id_key)
+        .long(installation_id_key)
+        .value_name("ID")
+        .help("ID of the GitHub App's installation on the watched repositories.")
         .takes_value(true);
 
     let owner_key = "owner";
     let owner_arg = Arg::with_name(owner_key)
         .short("o")
         .value_name("OWNER")
-        .required(true)
         .help("Owner of the repository to watch")
         .takes_value(true);
 
@@ -43,7 +508,6 @@ pub fn parse_args() -> Args {
     let repository_arg = Arg::with_name(repository_key)
         .short("r")
         .value_name("REPOSITORY")
-        .required(true)
         .help("Name of the repository to watch")
         .takes_value(true);
 
@@ -51,7 +515,6 @@ pub fn parse_args() -> Args {
     let branch_arg = Arg::with_name(branch_key)
         .short("b")
         .value_name("DEFAULT_BRANCH")
-        .required(true)
         .help("Name of the default branch to watch (typically master or develop). This must match the default branch on GitHub.")
         .takes_value(true);
 
@@ -59,7 +522,6 @@ pub fn parse_args() -> Args {
     let context_arg = Arg::with_name(context_key)
         .short("c")
         .value_name("CONTEXT")
-        .required(true)
         .help("Label to differentiate status from other statuses.")
         .takes_value(true);
 
@@ -67,7 +529,6 @@ pub fn parse_args() -> Args {
     let script_arg = Arg::with_name(script_key)
         .short("e")
         .value_name("FILE")
-        .required(true)
         .help("Bash script to run to test a commit.")
         .takes_value(true);
 
@@ -75,7 +536,6 @@ pub fn parse_args() -> Args {
     let region_arg = Arg::with_name(region_key)
         .long(region_key)
         .value_name("AWS_REGION")
-        .required(true)
         .help("AWS region of S3 bucket to save build logs.")
         .takes_value(true);
 
@@ -83,16 +543,174 @@ pub fn parse_args() -> Args {
     let bucket_arg = Arg::with_name(bucket_key)
         .long(bucket_key)
         .value_name("S3_BUCKET")
-        .required(true)
         .help("AWS bucket for build logs.")
         .takes_value(true);
 
+    let presign_expiry_key = "presign-expiry";
+    let presign_expiry_arg = Arg::with_name(presign_expiry_key)
+        .long(presign_expiry_key)
+        .value_name("SECONDS")
+        .help("Link statuses to presigned URLs for the build logs, valid for this long (at most a week), rather than to the S3 console.")
+        .takes_value(true);
+
+    let log_dir_key = "log-dir";
+    let log_dir_arg = Arg::with_name(log_dir_key)
+        .long(log_dir_key)
+        .value_name("DIRECTORY")
+        .help("Local directory to save build logs in, instead of an S3 bucket.")
+        .conflicts_with(bucket_key)
+        .takes_value(true);
+
+    let log_url_key = "log-url";
+    let log_url_arg = Arg::with_name(log_url_key)
+        .long(log_url_key)
+        .value_name("URL")
+        .help("Base URL the --log-dir directory is served from. Statuses link to file:// URLs without it.")
+        .takes_value(true);
+
+    let http_port_key = "http-port";
+    let http_port_arg = Arg::with_name(http_port_key)
+        .long(http_port_key)
+        .value_name("PORT")
+        .help("Serve build logs over HTTP on this port, and link statuses to them there.")
+        .takes_value(true);
+
+    let api_url_key = "api-url";
+    let api_url_arg = Arg::with_name(api_url_key)
+        .long(api_url_key)
+        .value_name("URL")
+        .help("Base URL of the GitHub API. For GitHub Enterprise this is https://<host>/api/v3. Defaults to https://api.github.com.")
+        .takes_value(true);
+
+    let git_url_key = "git-url";
+    let git_url_arg = Arg::with_name(git_url_key)
+        .long(git_url_key)
+        .value_name("URL")
+        .help("Base URL that repositories are cloned from. Defaults to the API URL without /api/v3, or https://github.com. Use ssh://git@<host> to clone over SSH with a key from the running ssh-agent.")
+        .takes_value(true);
+
+    let workspace_key = "workspace";
+    let workspace_arg = Arg::with_name(workspace_key)
+        .long(workspace_key)
+        .value_name("DIR")
+        .help("Directory to keep working copies, fetched objects, logs and the build history in. They are reused when crane restarts, so put this somewhere persistent. Defaults to /tmp/crane.")
+        .takes_value(true);
+
+    let fetch_depth_key = "fetch-depth";
+    let fetch_depth_arg = Arg::with_name(fetch_depth_key)
+        .long(fetch_depth_key)
+        .value_name("COMMITS")
+        .help("Only fetch this many commits of history, using the git command line. Saves time and space on large repositories, but pull requests that branched off further back than this can't be merged, and are given an error status.")
+        .takes_value(true);
+
+    let clean_key = "clean";
+    let clean_arg = Arg::with_name(clean_key)
+        .long(clean_key)
+        .value_name("POLICY")
+        .possible_values(&["none", "all"])
+        .help("Whether to delete untracked and ignored files from the working copy before each build. Defaults to none.")
+        .takes_value(true);
+
+    let keep_key = "keep";
+    let keep_arg = Arg::with_name(keep_key)
+        .long(keep_key)
+        .value_name("DIR")
+        .help("Directory to keep when cleaning, such as a build cache. Can be given more than once, and implies --clean all.")
+        .multiple(true)
+        .number_of_values(1)
+        .takes_value(true);
+
+    let ssh_key_file_key = "ssh-key-file";
+    let ssh_key_file_arg = Arg::with_name(ssh_key_file_key)
+        .long(ssh_key_file_key)
+        .value_name("FILE")
+        .help("Private key to clone over SSH with, instead of one from the ssh-agent.")
+        .takes_value(true);
+
+    let public_url_key = "public-url";
+    let public_url_arg = Arg::with_name(public_url_key)
+        .long(public_url_key)
+        .value_name("URL")
+        .help("Base URL at which others can reach the --http-port log server. Defaults to this host's name and the port.")
+        .takes_value(true);
+
+    let webhook_port_key = "webhook-port";
+    let webhook_port_arg = Arg::with_name(webhook_port_key)
+        .long(webhook_port_key)
+        .value_name("PORT")
+        .help("Receive GitHub push, pull_request and comment webhooks on this port. Polling then only reconciles missed events.")
+        .takes_value(true);
+
+    let webhook_secret_file_key = "webhook-secret-file";
+    let webhook_secret_file_arg = Arg::with_name(webhook_secret_file_key)
+        .long(webhook_secret_file_key)
+        .value_name("FILE")
+        .help("File containing the secret that webhooks are signed with. It can also be set with CRANE_WEBHOOK_SECRET.")
+        .takes_value(true);
+
+    let all_commits_key = "all-commits";
+    let all_commits_arg = Arg::with_name(all_commits_key)
+        .long(all_commits_key)
+        .help("Build every commit pushed since the last built commit, rather than only the latest.");
+
+    let pull_requests_key = "pull-requests";
+    let pull_requests_arg = Arg::with_name(pull_requests_key)
+        .long(pull_requests_key)
+        .help("Also build every open pull request, merged into the latest commit of its base branch. Statuses are posted on the head commit.");
+
+    let checks_key = "checks";
+    let checks_arg = Arg::with_name(checks_key)
+        .long(checks_key)
+        .help("Report builds as GitHub check runs, with log output and annotations, instead of commit statuses. The token must be allowed to write checks, which GitHub only grants to GitHub Apps. Agents sharing a context each create a check run, and all but the first mark theirs as superseded.");
+
+    let retry_command_key = "retry-command";
+    let retry_command_arg = Arg::with_name(retry_command_key)
+        .long(retry_command_key)
+        .value_name("COMMAND")
+        .help("Rebuild a commit when someone who can push to its repository comments '<COMMAND> <context>' on it or on its pull request, e.g. with '/crane retry'. Comments are picked up by polling, or from issue_comment and commit_comment webhooks.")
+        .takes_value(true);
+
+    let timeout_key = "timeout";
+    let timeout_arg = Arg::with_name(timeout_key)
+        .long(timeout_key)
+        .value_name("SECONDS")
+        .help("Kill a build and report an error if it runs for longer than this.")
+        .takes_value(true);
+
+    let orphan_age_key = "orphan-age";
+    let orphan_age_arg = Arg::with_name(orphan_age_key)
+        .long(orphan_age_key)
+        .value_name("SECONDS")
+        .help("Treat a pending status as orphaned once the agent that posted it has sent no heartbeat for this long. Must be over four minutes. Defaults to ten minutes. Check runs can't be refreshed, so with --checks a check run is orphaned once it started this long ago, and this must be longer than any build.")
+        .takes_value(true);
+
+    let orphans_key = "orphans";
+    let orphans_arg = Arg::with_name(orphans_key)
+        .long(orphans_key)
+        .value_name("POLICY")
+        .possible_values(&["rebuild", "error"])
+        .help("What to do with builds left pending by an agent that stopped, including this one before a restart. Defaults to rebuild.")
+        .takes_value(true);
+
+    let jobs_key = "jobs";
+    let jobs_arg = Arg::with_name(jobs_key)
+        .long(jobs_key)
+        .short("j")
+        .value_name("N")
+        .help("Number of builds to run at once. Each runs in its own working copy. Defaults to 1.")
+        .takes_value(true);
+
     let matches = App::new("Crane")
         .version("0.1")
         .author("Zach Bray <zachbray@googlemail.com>")
         .about("Watches, builds and updates GitHub statuses.")
+        .arg(config_arg)
         .arg(user_arg)
         .arg(token_arg)
+        .arg(token_file_arg)
+        .arg(app_id_arg)
+        .arg(app_key_file_arg)
+        .arg(installation_id_arg)
         .arg(owner_arg)
         .arg(repository_arg)
         .arg(branch_arg)
@@ -100,17 +718,37 @@ pub fn parse_args() -> Args {
         .arg(script_arg)
         .arg(region_arg)
         .arg(bucket_arg)
+        .arg(presign_expiry_arg)
+        .arg(log_dir_arg)
+        .arg(log_url_arg)
+        .arg(http_port_arg)
+        .arg(public_url_arg)
+        .arg(api_url_arg)
+        .arg(git_url_arg)
+        .arg(ssh_key_file_arg)
+        .arg(workspace_arg)
+        .arg(clean_arg)
+        .arg(fetch_depth_arg)
+        .arg(keep_arg)
+        .arg(webhook_port_arg)
+        .arg(webhook_secret_file_arg)
+        .arg(all_commits_arg)
+        .arg(pull_requests_arg)
+        .arg(checks_arg)
+        .arg(retry_command_arg)
+        .arg(timeout_arg)
+        .arg(orphan_age_arg)
+        .arg(orphans_arg)
+        .arg(jobs_arg)
         .get_matches();
 
-    Args {
-        user: matches.value_of(&user_key).unwrap().to_string(),
-        token: matches.value_of(&token_key).unwrap().to_string(),
-        owner: matches.value_of(&owner_key).unwrap().to_string(),
-        repository: matches.value_of(&repository_key).unwrap().to_string(),
-        branch: matches.value_of(&branch_key).unwrap().to_string(),
-        context: matches.value_of(&context_key).unwrap().to_string(),
-        script: matches.value_of(&script_key).unwrap().to_string(),
-        region: matches.value_of(&region_key).unwrap().to_string(),
-        bucket: matches.value_of(&bucket_key).unwrap().to_string(),
-    }
+    let file_settings = match matches.value_of(config_key) {
+        Some(path) => Settings::from_file(path)?,
+        None => Settings::default(),
+    };
+
+    Settings::from_matches(&matches)?
+        .or(Settings::from_env()?)
+        .or(file_settings)
+        .into_args()
 }
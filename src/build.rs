@@ -0,0 +1,221 @@
+use failure::Error;
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+
+const TAIL_LINES: usize = 200;
+const MAX_RETAINED_LOGS: usize = 10;
+
+/// A running build script whose stdout and stderr are streamed to log files on disk as they are
+/// produced, rather than buffered in memory until the script exits.
+pub struct Build {
+    child: Child,
+    tail: Arc<Mutex<VecDeque<String>>>,
+    readers: Vec<JoinHandle<()>>,
+    stdout: LogFile,
+    stderr: LogFile,
+}
+
+impl Build {
+    pub fn start(script: &str, log_dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(log_dir)?;
+        let stdout = LogFile::new("stdout", log_dir.join("stdout.txt"));
+        let stderr = LogFile::new("stderr", log_dir.join("stderr.txt"));
+
+        let mut command = Command::new("bash");
+        command.arg(script)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Run the script in its own process group so anything it spawns can be killed with it.
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setpgid(0, 0) == 0 {
+                    Ok(())
+                } else {
+                    Err(io::Error::last_os_error())
+                }
+            });
+        }
+        let mut child = command.spawn()?;
+
+        let tail = Arc::new(Mutex::new(VecDeque::new()));
+        let mut readers = vec![];
+        if let Some(pipe) = child.stdout.take() {
+            readers.push(stream_to_file(pipe, File::create(&stdout.path)?, tail.clone()));
+        }
+        if let Some(pipe) = child.stderr.take() {
+            readers.push(stream_to_file(pipe, File::create(&stderr.path)?, tail.clone()));
+        }
+
+        Ok(Build {
+            child,
+            tail,
+            readers,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Returns the exit status once the script has finished and all of its output has been
+    /// written to disk. Anything the script left running in the background is killed, as it would
+    /// otherwise hold the output open indefinitely.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>, Error> {
+        let status = self.child.try_wait()?;
+        if status.is_some() {
+            // The group outlives its leader for as long as any of its members are running.
+            unsafe { libc::killpg(self.child.id() as libc::pid_t, libc::SIGKILL) };
+            for reader in self.readers.drain(..) {
+                reader.join().unwrap_or(());
+            }
+        }
+        Ok(status)
+    }
+
+    /// Kills the script along with every process in its process group.
+    pub fn kill(&mut self) -> Result<(), Error> {
+        if unsafe { libc::killpg(self.child.id() as libc::pid_t, libc::SIGKILL) } != 0 {
+            self.child.kill()?;
+        }
+        self.child.wait()?;
+        for reader in self.readers.drain(..) {
+            reader.join().unwrap_or(());
+        }
+        Ok(())
+    }
+
+    /// The most recent lines of output, with stdout and stderr interleaved.
+    pub fn tail(&self) -> Vec<String> {
+        self.tail.lock()
+            .map(|tail| tail.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Reads back everything the script wrote a line at a time, stdout followed by stderr.
+    pub fn log_lines(&self) -> Result<impl Iterator<Item = String>, Error> {
+        Ok(self.stdout.lines()?.chain(self.stderr.lines()?))
+    }
+
+    pub fn logs(&mut self) -> [&mut LogFile; 2] {
+        [&mut self.stdout, &mut self.stderr]
+    }
+}
+
+impl Drop for Build {
+    /// Kills the script if it is still running, so a build that is given up on midway doesn't
+    /// carry on without anything watching it.
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            self.kill().unwrap_or(());
+        }
+    }
+}
+
+fn stream_to_file<R>(pipe: R, mut file: File, tail: Arc<Mutex<VecDeque<String>>>) -> JoinHandle<()>
+    where R: Read + Send + 'static {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut line = vec![];
+        while let Ok(length) = reader.read_until(b'\n', &mut line) {
+            if length == 0 || file.write_all(&line).is_err() {
+                break;
+            }
+            if let Ok(mut tail) = tail.lock() {
+                if tail.len() >= TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(String::from_utf8_lossy(&line).trim_end().to_string());
+            }
+            line.clear();
+        }
+    })
+}
+
+/// One of a build's output streams on disk, tracking how much of it has already been uploaded.
+pub struct LogFile {
+    pub name: &'static str,
+    path: PathBuf,
+    uploaded_length: u64,
+    chunk_count: u32,
+}
+
+impl LogFile {
+    fn new(name: &'static str, path: PathBuf) -> Self {
+        LogFile {
+            name,
+            path,
+            uploaded_length: 0,
+            chunk_count: 0,
+        }
+    }
+
+    /// Reads everything written since the last chunk was uploaded, along with its sequence number.
+    /// The same chunk is read again until it is marked as uploaded.
+    pub fn next_chunk(&self) -> Result<Option<(u32, Vec<u8>)>, Error> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.uploaded_length))?;
+        let mut chunk = vec![];
+        file.read_to_end(&mut chunk)?;
+        if chunk.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((self.chunk_count + 1, chunk)))
+    }
+
+    /// Records that a chunk of `length` bytes read with `next_chunk` has been uploaded.
+    pub fn mark_uploaded(&mut self, length: usize) {
+        self.uploaded_length += length as u64;
+        self.chunk_count += 1;
+    }
+
+    fn lines(&self) -> Result<impl Iterator<Item = String>, Error> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        Ok(reader.split(b'\n')
+            .filter_map(Result::ok)
+            .map(|line| String::from_utf8_lossy(&line).to_string()))
+    }
+
+    pub fn contents(&self) -> Result<Vec<u8>, Error> {
+        let mut contents = vec![];
+        File::open(&self.path)?.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+}
+
+/// Removes all but the most recently modified build log directories beneath `log_root`.
+pub fn roll_logs(log_root: &Path) -> Result<(), Error> {
+    if !log_root.exists() {
+        return Ok(());
+    }
+    let mut log_dirs = vec![];
+    for entry in fs::read_dir(log_root)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            log_dirs.push((entry.metadata()?.modified()?, entry.path()));
+        }
+    }
+    log_dirs.sort();
+    let excess = log_dirs.len().saturating_sub(MAX_RETAINED_LOGS);
+    for (_, path) in log_dirs.into_iter().take(excess) {
+        fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use crate::hub::requests::Annotation;
+use crate::hub::requests::CheckRunOutput;
+use std::path::Path;
+
+/// GitHub rejects requests carrying more annotations than this.
+const MAX_ANNOTATIONS: usize = 50;
+/// GitHub rejects check run output text longer than this.
+const MAX_TEXT_LENGTH: usize = 65535;
+
+/// Describes a build for its check run, with the tail of its log and annotations for any
+/// compiler-style messages in its output.
+pub fn output(title: &str, summary: &str, tail: &[String], annotations: Vec<Annotation>) -> CheckRunOutput {
+    CheckRunOutput {
+        title: title.to_string(),
+        summary: summary.to_string(),
+        text: if tail.is_empty() { None } else { Some(log_text(tail)) },
+        annotations,
+    }
+}
+
+fn log_text(tail: &[String]) -> String {
+    let fence = "```";
+    let room = MAX_TEXT_LENGTH - 2 * (fence.len() + 1);
+    let mut lines = vec![];
+    let mut length = 0;
+    for line in tail.iter().rev() {
+        length += line.len() + 1;
+        if length > room {
+            break;
+        }
+        lines.push(line.as_str());
+    }
+    lines.reverse();
+    format!("{}\n{}\n{}", fence, lines.join("\n"), fence)
+}
+
+/// Finds `file:line: message` and `file:line:column: message` lines in a log that refer to files
+/// in the checkout at `checkout`.
+pub fn annotations<I>(lines: I, checkout: &str) -> Vec<Annotation> where I: Iterator<Item = String> {
+    lines.filter_map(|line| annotation(&line, checkout))
+        .take(MAX_ANNOTATIONS)
+        .collect()
+}
+
+fn annotation(line: &str, checkout: &str) -> Option<Annotation> {
+    let mut parts = line.splitn(4, ':');
+    let path = parts.next()?.trim();
+    let line_number: u32 = parts.next()?.trim().parse().ok()?;
+    let rest = parts.next()?;
+    let message = match (rest.trim().parse::<u32>(), parts.next()) {
+        (Ok(_), Some(message)) => message.to_string(),
+        (Ok(_), None) => return None,
+        (Err(_), Some(more)) => format!("{}:{}", rest, more),
+        (Err(_), None) => rest.to_string(),
+    };
+    let message = message.trim();
+    let checkout_prefix = format!("{}/", checkout.trim_end_matches('/'));
+    let path = if path.starts_with(&checkout_prefix) { &path[checkout_prefix.len()..] } else { path };
+    let path = path.trim_start_matches("./");
+    if message.is_empty() || line_number == 0 || path.is_empty() || path.contains(char::is_whitespace)
+        || Path::new(path).is_absolute() || !Path::new(checkout).join(path).is_file() {
+        return None;
+    }
+    let lowercase_message = message.to_lowercase();
+    let annotation_level = if lowercase_message.starts_with("warning") {
+        "warning"
+    } else if lowercase_message.starts_with("note") {
+        "notice"
+    } else {
+        "failure"
+    };
+    Some(Annotation {
+        path: path.to_string(),
+        start_line: line_number,
+        end_line: line_number,
+        annotation_level,
+        message: message.to_string(),
+    })
+}
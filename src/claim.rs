@@ -0,0 +1,300 @@
+use crate::hub::common::State;
+use crate::hub::responses::CheckRun;
+use crate::hub::responses::Status;
+use chrono::DateTime;
+use failure::Error;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// How often an agent re-posts its pending status while building, to show it is still alive.
+pub const HEARTBEAT_PERIOD: Duration = Duration::from_secs(2 * 60);
+/// How long a claim lasts without a heartbeat, by default, before the build is orphaned.
+pub const DEFAULT_ORPHAN_AGE: Duration = Duration::from_secs(10 * 60);
+const AGENT_ID_FILE: &str = "agent-id";
+const MARKER_PREFIX: &str = "[crane ";
+const MARKER_SUFFIX: &str = "]";
+/// The conclusion given to check runs that were superseded by another, and so don't count.
+pub const SUPERSEDED_CONCLUSION: &str = "neutral";
+
+/// Who, if anyone, is building a commit for a context, going by its statuses or check runs.
+///
+/// Agents claim a build by posting a pending status carrying their agent ID, and keep the claim
+/// alive by posting it again every `HEARTBEAT_PERIOD`. When several agents hold live claims, the
+/// one that claimed first builds and the others back off. A claim that goes without a heartbeat
+/// for long enough is orphaned, and the build may be taken over.
+///
+/// In checks mode, the claim is instead a check run with the agent ID as its external ID. Check
+/// runs can't be refreshed, so a claim is orphaned once its check run started long enough ago.
+#[derive(Debug)]
+pub enum Claim {
+    /// The build has finished, with this result.
+    Finished(State),
+    /// The agent with this ID is building the commit, and last posted its claim at this time.
+    Held(String, SystemTime),
+    /// Agents claimed the commit but have all stopped sending heartbeats, leaving it pending.
+    Orphaned,
+    /// No agent has claimed the commit.
+    Unclaimed,
+}
+
+/// What to do with a build orphaned by an agent that stopped mid-build.
+#[derive(Clone, Copy, Debug)]
+pub enum OrphanPolicy {
+    /// Build the commit again.
+    Rebuild,
+    /// Give up on the commit, marking it with an error status.
+    MarkError,
+}
+
+/// Reads this agent's ID from the workspace, choosing one the first time. Keeping it across
+/// restarts lets the agent recognise builds it abandoned when it last stopped.
+pub fn load_agent_id(workspace: &Path) -> Result<String, Error> {
+    let path = workspace.join(AGENT_ID_FILE);
+    if let Ok(agent_id) = fs::read_to_string(&path) {
+        if !agent_id.trim().is_empty() {
+            return Ok(agent_id.trim().to_string());
+        }
+    }
+    let agent_id = format!("{:08x}", rand::random::<u32>());
+    fs::create_dir_all(workspace)?;
+    fs::write(&path, &agent_id)?;
+    Ok(agent_id)
+}
+
+/// The text added to pending status descriptions to mark them as claims by `agent_id`.
+pub fn marker(agent_id: &str) -> String {
+    format!("{}{}{}", MARKER_PREFIX, agent_id, MARKER_SUFFIX)
+}
+
+/// Works out the claim from the statuses of one context, newest first. Claims not refreshed
+/// within `orphan_age` are ignored, and pending statuses without a marker are taken to be claims
+/// by an unknown agent.
+pub fn from_statuses(statuses: Vec<Status>, now: SystemTime, orphan_age: Duration) -> Claim {
+    // For each agent, when it was last heard from and the ID of its first claim.
+    let mut claims: HashMap<String, (SystemTime, u64)> = HashMap::new();
+    for status in statuses {
+        match status.state {
+            State::Pending => {}
+            state if claims.is_empty() => return Claim::Finished(state),
+            _ => break,
+        }
+        let agent_id = status.description.as_ref()
+            .and_then(|description| claimant(description))
+            .unwrap_or("unknown")
+            .to_string();
+        let claim = claims.entry(agent_id).or_insert((parse_time(&status.created_at), status.id));
+        claim.1 = status.id.min(claim.1);
+    }
+    held_or_orphaned(claims, now, orphan_age)
+}
+
+/// Works out the claim from the check runs of one context, in any order. Check runs that were
+/// superseded are ignored, and open ones without an external ID are taken to be claims by an
+/// unknown agent.
+pub fn from_check_runs(mut check_runs: Vec<CheckRun>, now: SystemTime, orphan_age: Duration) -> Claim {
+    check_runs.sort_by_key(|check_run| Reverse(check_run.id));
+    let mut claims: HashMap<String, (SystemTime, u64)> = HashMap::new();
+    for check_run in check_runs {
+        let state = match (check_run.status.as_str(), check_run.conclusion.as_ref().map(String::as_str)) {
+            (_, Some(SUPERSEDED_CONCLUSION)) => continue,
+            ("completed", Some("success")) => State::Success,
+            ("completed", Some("failure")) => State::Failure,
+            ("completed", _) => State::Error,
+            _ => State::Pending,
+        };
+        match state {
+            State::Pending => {}
+            _ if claims.is_empty() => return Claim::Finished(state),
+            _ => break,
+        }
+        let agent_id = check_run.external_id
+            .filter(|external_id| !external_id.is_empty())
+            .unwrap_or_else(|| "unknown".to_string());
+        let started_at = check_run.started_at.as_ref().map_or(UNIX_EPOCH, |started_at| parse_time(started_at));
+        let claim = claims.entry(agent_id).or_insert((started_at, check_run.id));
+        claim.1 = check_run.id.min(claim.1);
+    }
+    held_or_orphaned(claims, now, orphan_age)
+}
+
+/// Picks the earliest of the live `claims`, which give when each agent was last heard from and
+/// the ID of its first claim.
+fn held_or_orphaned(claims: HashMap<String, (SystemTime, u64)>, now: SystemTime, orphan_age: Duration) -> Claim {
+    if claims.is_empty() {
+        return Claim::Unclaimed;
+    }
+    claims.into_iter()
+        .filter(|(_, (last_seen, _))| now.duration_since(*last_seen).map_or(true, |age| age < orphan_age))
+        .min_by_key(|(_, (_, first_claim))| *first_claim)
+        .map_or(Claim::Orphaned, |(agent_id, (last_seen, _))| Claim::Held(agent_id, last_seen))
+}
+
+/// Reads one of GitHub's timestamps, taking any it can't read to be long past.
+pub fn parse_time(time: &str) -> SystemTime {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| UNIX_EPOCH + Duration::from_secs(time.timestamp() as u64))
+        .unwrap_or(UNIX_EPOCH)
+}
+
+fn claimant(description: &str) -> Option<&str> {
+    let start = description.rfind(MARKER_PREFIX)? + MARKER_PREFIX.len();
+    let length = description[start..].find(MARKER_SUFFIX)?;
+    Some(&description[start..start + length])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    const NOW_SECS: u64 = 1_500_000_000;
+    const ORPHAN_AGE: Duration = Duration::from_secs(10 * 60);
+
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(NOW_SECS)
+    }
+
+    fn status(id: u64, state: State, age_secs: u64, description: Option<&str>) -> Status {
+        let created_at = DateTime::<Utc>::from(now() - Duration::from_secs(age_secs));
+        Status {
+            id,
+            created_at: created_at.to_rfc3339(),
+            state,
+            target_url: None,
+            description: description.map(str::to_string),
+            context: Some("crane".to_string()),
+        }
+    }
+
+    fn claim(id: u64, agent_id: &str, age_secs: u64) -> Status {
+        let description = format!("Building on host {}", marker(agent_id));
+        status(id, State::Pending, age_secs, Some(&description))
+    }
+
+    fn check_run(id: u64, agent_id: &str, conclusion: Option<&str>, age_secs: u64) -> CheckRun {
+        let started_at = DateTime::<Utc>::from(now() - Duration::from_secs(age_secs));
+        CheckRun {
+            id,
+            external_id: Some(agent_id.to_string()),
+            status: if conclusion.is_some() { "completed" } else { "in_progress" }.to_string(),
+            conclusion: conclusion.map(str::to_string),
+            started_at: Some(started_at.to_rfc3339()),
+        }
+    }
+
+    fn held_by(claim: Claim) -> Option<String> {
+        match claim {
+            Claim::Held(agent_id, _) => Some(agent_id),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn no_statuses_is_unclaimed() {
+        match from_statuses(vec![], now(), ORPHAN_AGE) {
+            Claim::Unclaimed => {}
+            other => panic!("Expected unclaimed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn result_is_finished() {
+        let statuses = vec![status(2, State::Success, 60, Some("Succeeded in 1m")), claim(1, "a", 120)];
+        match from_statuses(statuses, now(), ORPHAN_AGE) {
+            Claim::Finished(State::Success) => {}
+            other => panic!("Expected finished, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn earliest_live_claim_wins() {
+        let statuses = vec![claim(13, "a", 10), claim(12, "b", 20), claim(11, "a", 30)];
+        assert_eq!(held_by(from_statuses(statuses, now(), ORPHAN_AGE)), Some("a".to_string()));
+    }
+
+    #[test]
+    fn claims_before_the_last_result_are_ignored() {
+        let statuses = vec![claim(13, "b", 10), status(12, State::Failure, 20, None), claim(11, "a", 30)];
+        assert_eq!(held_by(from_statuses(statuses, now(), ORPHAN_AGE)), Some("b".to_string()));
+    }
+
+    #[test]
+    fn stale_claim_gives_way_to_live_one() {
+        let statuses = vec![claim(12, "b", 60), claim(11, "a", 20 * 60)];
+        assert_eq!(held_by(from_statuses(statuses, now(), ORPHAN_AGE)), Some("b".to_string()));
+    }
+
+    #[test]
+    fn held_claim_was_last_seen_at_latest_heartbeat() {
+        let statuses = vec![claim(13, "a", 10), claim(12, "b", 20), claim(11, "a", 30)];
+        match from_statuses(statuses, now(), ORPHAN_AGE) {
+            Claim::Held(_, last_seen) => assert_eq!(last_seen, now() - Duration::from_secs(10)),
+            other => panic!("Expected held, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn all_claims_stale_is_orphaned() {
+        let statuses = vec![claim(12, "b", 15 * 60), claim(11, "a", 20 * 60)];
+        match from_statuses(statuses, now(), ORPHAN_AGE) {
+            Claim::Orphaned => {}
+            other => panic!("Expected orphaned, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pending_status_without_marker_is_held_by_unknown_agent() {
+        let statuses = vec![status(11, State::Pending, 60, Some("Building elsewhere"))];
+        assert_eq!(held_by(from_statuses(statuses, now(), ORPHAN_AGE)), Some("unknown".to_string()));
+        let statuses = vec![status(11, State::Pending, 60, None)];
+        assert_eq!(held_by(from_statuses(statuses, now(), ORPHAN_AGE)), Some("unknown".to_string()));
+    }
+
+    #[test]
+    fn earliest_open_check_run_wins() {
+        let check_runs = vec![check_run(12, "b", None, 10), check_run(11, "a", None, 20)];
+        assert_eq!(held_by(from_check_runs(check_runs, now(), ORPHAN_AGE)), Some("a".to_string()));
+    }
+
+    #[test]
+    fn latest_completed_check_run_is_finished() {
+        let check_runs = vec![check_run(11, "a", Some("success"), 120), check_run(12, "a", Some("failure"), 60)];
+        match from_check_runs(check_runs, now(), ORPHAN_AGE) {
+            Claim::Finished(State::Failure) => {}
+            other => panic!("Expected failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn superseded_check_runs_are_ignored() {
+        let check_runs = vec![check_run(12, "b", Some(SUPERSEDED_CONCLUSION), 10), check_run(11, "a", None, 20)];
+        assert_eq!(held_by(from_check_runs(check_runs, now(), ORPHAN_AGE)), Some("a".to_string()));
+        let check_runs = vec![check_run(11, "a", Some(SUPERSEDED_CONCLUSION), 10)];
+        match from_check_runs(check_runs, now(), ORPHAN_AGE) {
+            Claim::Unclaimed => {}
+            other => panic!("Expected unclaimed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn check_run_started_long_ago_is_orphaned() {
+        let check_runs = vec![check_run(11, "a", None, 20 * 60)];
+        match from_check_runs(check_runs, now(), ORPHAN_AGE) {
+            Claim::Orphaned => {}
+            other => panic!("Expected orphaned, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn claimant_is_read_from_last_marker() {
+        assert_eq!(claimant("Building on host [crane 0123abcd]"), Some("0123abcd"));
+        assert_eq!(claimant("Building [crane fake] on host [crane real]"), Some("real"));
+        assert_eq!(claimant("Building on host"), None);
+        assert_eq!(claimant("Building on host [crane unterminated"), None);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::hub::COMMENTS_PER_PAGE;
+use crate::hub::GitHubClient;
+use crate::hub::RepoLocator;
+use crate::hub::responses::Comment;
+use chrono::Utc;
+use failure::Error;
+
+/// GitHub's timestamps, which sort in time order when compared as text.
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// What a comment was posted on.
+pub enum Subject {
+    PullRequest(u32),
+    Commit(String),
+}
+
+/// Where polling for comments on one repository left off, so that each comment is only seen once.
+/// Comments posted before polling started are never seen.
+#[derive(Clone)]
+pub struct CommentCursor {
+    started: String,
+    /// Issue comments last changed before this time have been seen.
+    issue_comments_since: String,
+    last_issue_comment: u64,
+    /// The page of commit comments that the newest one seen is on. Commit comments can only be
+    /// listed from the oldest, so the first poll pages through all of them to find the end.
+    commit_comments_page: u32,
+    last_commit_comment: u64,
+}
+
+impl CommentCursor {
+    pub fn new() -> Self {
+        let now = Utc::now().format(TIME_FORMAT).to_string();
+        CommentCursor {
+            started: now.to_string(),
+            issue_comments_since: now,
+            last_issue_comment: 0,
+            commit_comments_page: 1,
+            last_commit_comment: 0,
+        }
+    }
+
+    /// Fetches the issue, pull request and commit comments posted since the last poll, along with
+    /// the cursor to poll from once they have all been dealt with.
+    pub fn poll(&self, github: &GitHubClient, repo: &RepoLocator) -> Result<(Vec<Comment>, CommentCursor), Error> {
+        let mut comments = vec![];
+        let mut next = self.clone();
+        let mut page = 1;
+        loop {
+            let issue_comments = github.get_issue_comments(repo, &self.issue_comments_since, page)?;
+            let is_last_page = (issue_comments.len() as u32) < COMMENTS_PER_PAGE;
+            for comment in issue_comments {
+                next.issue_comments_since = next.issue_comments_since.max(comment.updated_at.to_string());
+                // Older comments come back again whenever they are edited.
+                if comment.id > next.last_issue_comment && comment.created_at >= self.started {
+                    next.last_issue_comment = comment.id;
+                    comments.push(comment);
+                }
+            }
+            if is_last_page {
+                break;
+            }
+            page += 1;
+        }
+        loop {
+            let commit_comments = github.get_commit_comments(repo, next.commit_comments_page)?;
+            let is_last_page = (commit_comments.len() as u32) < COMMENTS_PER_PAGE;
+            for comment in commit_comments {
+                if comment.id > next.last_commit_comment {
+                    next.last_commit_comment = comment.id;
+                    if comment.created_at >= self.started {
+                        comments.push(comment);
+                    }
+                }
+            }
+            if is_last_page {
+                break;
+            }
+            next.commit_comments_page += 1;
+        }
+        Ok((comments, next))
+    }
+}
+
+/// Checks whether any line of a comment is `command` followed by `context`, such as
+/// `/crane retry ci/crane`.
+pub fn requests_retry(body: &str, command: &str, context: &str) -> bool {
+    let expected = format!("{} {}", command.split_whitespace().collect::<Vec<_>>().join(" "), context);
+    body.lines()
+        .any(|line| line.split_whitespace().collect::<Vec<_>>().join(" ") == expected)
+}
+
+/// Works out what a comment was posted on. Comments on issues that aren't pull requests have no
+/// commit to build, so have no subject.
+pub fn subject(comment: &Comment) -> Option<Subject> {
+    if let Some(sha) = &comment.commit_id {
+        return Some(Subject::Commit(sha.to_string()));
+    }
+    // Links to pull request comments look like https://github.com/owner/repo/pull/12#issuecomment-34.
+    let page_url = comment.html_url.split('#').next()?;
+    let mut segments = page_url.rsplit('/');
+    let number = segments.next()?.parse().ok()?;
+    match segments.next()? {
+        "pull" => Some(Subject::PullRequest(number)),
+        _ => None,
+    }
+}
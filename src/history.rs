@@ -0,0 +1,92 @@
+use crate::hub::PullRequestLocator;
+use crate::hub::common::State;
+use chrono::DateTime;
+use chrono::Utc;
+use failure::Error;
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// A build run by an agent, from start to finish.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BuildRecord {
+    /// The label of the target built.
+    pub target: String,
+    pub context: String,
+    pub sha: String,
+    pub pull_request: Option<PullRequestLocator>,
+    /// The agent that ran the build.
+    pub agent_id: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub state: State,
+    /// The exit code of the build script, which has none if it was killed.
+    pub exit_code: Option<i32>,
+    /// Where the complete logs were put in the target's log store.
+    pub log_keys: Vec<String>,
+}
+
+/// Every build this agent has run, kept in the workspace as one JSON record per line.
+///
+/// Records are only ever appended, so a crash can at worst cut the last one short. Lines that
+/// can't be read are skipped.
+pub struct BuildHistory {
+    path: PathBuf,
+    file: File,
+}
+
+impl BuildHistory {
+    pub fn open(workspace: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(workspace)?;
+        let path = workspace.join(HISTORY_FILE);
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(&path)?;
+        // Start afresh on a new line if the last record was cut short.
+        let length = file.metadata()?.len();
+        if length > 0 {
+            let mut last = [0u8];
+            file.seek(SeekFrom::Start(length - 1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                file.write_all(b"\n")?;
+            }
+        }
+        Ok(BuildHistory { path, file })
+    }
+
+    /// Reads the last `count` records, oldest first.
+    pub fn load(&self, count: usize) -> Result<Vec<BuildRecord>, Error> {
+        let mut records = VecDeque::new();
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            if let Ok(record) = serde_json::from_str(&line?) {
+                if records.len() >= count {
+                    records.pop_front();
+                }
+                records.push_back(record);
+            }
+        }
+        Ok(records.into_iter().collect())
+    }
+
+    pub fn append(&mut self, record: &BuildRecord) -> Result<(), Error> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+pub fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339()
+}
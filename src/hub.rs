@@ -1,8 +1,41 @@
+use crate::args::AuthArgs;
+use chrono::DateTime;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::pkey::Private;
+use openssl::sign::Signer;
 use reqwest::Client;
+use reqwest::Request;
+use reqwest::RequestBuilder;
+use reqwest::Response;
+use reqwest::StatusCode;
 use reqwest::header;
+use serde::de::DeserializeOwned;
+use serde::de::IgnoredAny;
+use std::collections::HashMap;
+use std::fmt;
 use std::result;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use crate::hub::common::State;
+use crate::hub::requests::CreateCheckRunRequest;
+use crate::hub::requests::JwtClaims;
+use crate::hub::requests::ReactionRequest;
+use crate::hub::requests::UpdateCheckRunRequest;
+use crate::hub::responses::CheckRun;
+use crate::hub::responses::CheckRunsResponse;
+use crate::hub::responses::Comment;
+use crate::hub::responses::CommentsResponse;
 use crate::hub::responses::CommitsResponse;
+use crate::hub::responses::InstallationTokenResponse;
+use crate::hub::responses::PermissionResponse;
+use crate::hub::responses::PullRequest;
+use crate::hub::responses::PullRequestsResponse;
 use crate::hub::requests::SetStatusRequest;
+use crate::hub::responses::Status;
 use crate::hub::responses::StatusesResponse;
 
 #[derive(Fail, Debug)]
@@ -16,22 +49,139 @@ pub enum GitHubError {
     HttpError {
         inner_error: reqwest::Error,
     },
+
+    #[fail(display = "Invalid response: {}", inner_error)]
+    InvalidResponse {
+        inner_error: serde_json::Error,
+    },
+
+    #[fail(display = "Could not sign GitHub App token: {}", inner_error)]
+    SigningError {
+        inner_error: ErrorStack,
+    },
+
+    #[fail(display = "{} is not a valid token expiry time", value)]
+    InvalidExpiry {
+        value: String,
+    },
+
+    #[fail(display = "GitHub API returned {}: {}", status, message)]
+    ApiError {
+        status: u16,
+        message: String,
+    },
+
+    #[fail(display = "GitHub API rate limit exceeded, retrying in {}s", seconds)]
+    RateLimited {
+        seconds: u64,
+    },
 }
 
 pub struct GitHubClient {
     client: Client,
+    api_url: String,
+    credentials: Credentials,
+    cache: Mutex<HashMap<String, CachedResponse>>,
+    rate_limit: Mutex<RateLimit>,
+}
+
+enum Credentials {
+    Token { user: String, token: String },
+    App { app_id: u64, key: PKey<Private>, installation_id: u64, installation_token: Mutex<Option<InstallationToken>> },
+}
+
+/// A token granting a GitHub App access to the repositories it is installed on, for about an hour.
+struct InstallationToken {
+    token: String,
+    expires_at: SystemTime,
+}
+
+/// A response body kept so that a later request for the same URL can be made conditional on it
+/// having changed. GitHub doesn't count `304 Not Modified` responses against the rate limit.
+struct CachedResponse {
+    etag: String,
+    body: String,
+}
+
+/// The API quota reported by the most recent response.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RateLimit {
+    pub limit: Option<u32>,
+    pub remaining: Option<u32>,
+    /// When the quota is next refilled.
+    pub reset: Option<SystemTime>,
+    /// When GitHub asked us to try again after throttling a request.
+    pub retry_after: Option<SystemTime>,
+}
+
+impl RateLimit {
+    /// The time before which no more requests should be made, if the quota has run out or GitHub
+    /// has asked us to slow down.
+    pub fn resume_time(&self) -> Option<SystemTime> {
+        let now = SystemTime::now();
+        let exhausted_until = match self.remaining {
+            Some(0) => self.reset,
+            _ => None,
+        };
+        exhausted_until.into_iter()
+            .chain(self.retry_after)
+            .filter(|time| *time > now)
+            .max()
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.remaining, self.limit) {
+            (Some(remaining), Some(limit)) => write!(f, "{}/{}", remaining, limit),
+            (Some(remaining), None) => write!(f, "{}", remaining),
+            _ => write!(f, "Unknown"),
+        }
+    }
 }
 
 pub type Result<T> = result::Result<T, GitHubError>;
 
-const BASE_URL: &'static str = "https://api.github.com";
+pub const DEFAULT_API_URL: &'static str = "https://api.github.com";
+const COMMITS_PER_PAGE: u32 = 30;
+const PULL_REQUESTS_PER_PAGE: u32 = 100;
+const STATUSES_PER_PAGE: u32 = 100;
+pub const COMMENTS_PER_PAGE: u32 = 100;
+/// How far back to look through the statuses of one commit before giving up.
+const MAX_STATUS_PAGES: u32 = 10;
+const MAX_CACHED_RESPONSES: usize = 1000;
+/// The Checks API is still a preview and has to be asked for explicitly.
+const CHECKS_MEDIA_TYPE: &'static str = "application/vnd.github.antiope-preview+json";
+/// As is the GitHub Apps API.
+const APPS_MEDIA_TYPE: &'static str = "application/vnd.github.machine-man-preview+json";
+/// As is the Reactions API.
+const REACTIONS_MEDIA_TYPE: &'static str = "application/vnd.github.squirrel-girl-preview+json";
+/// Git accepts installation tokens as the password of this user.
+const APP_GIT_USER: &'static str = "x-access-token";
+/// Installation tokens are replaced this long before they expire, so none expires mid-request.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+/// GitHub refuses app tokens that are valid for more than ten minutes.
+const JWT_LIFETIME: Duration = Duration::from_secs(9 * 60);
+/// App tokens are backdated to allow for our clock being ahead of GitHub's.
+const JWT_BACKDATE: Duration = Duration::from_secs(60);
 
 impl GitHubClient {
-    pub fn new(token: &str) -> Result<Self> {
+    /// Connects to the API at `api_url`, which for GitHub Enterprise is `https://<host>/api/v3`.
+    pub fn new(auth: &AuthArgs, api_url: &str) -> Result<Self> {
+        let credentials = match auth {
+            AuthArgs::Token { user, token } => Credentials::Token {
+                user: user.to_string(),
+                token: token.to_string(),
+            },
+            AuthArgs::App { app_id, private_key, installation_id } => Credentials::App {
+                app_id: *app_id,
+                key: PKey::private_key_from_pem(private_key)
+                    .map_err(|inner_error| GitHubError::SigningError { inner_error })?,
+                installation_id: *installation_id,
+                installation_token: Mutex::new(None),
+            },
+        };
         let mut headers = header::HeaderMap::new();
-        let auth_token = header::HeaderValue::from_str(&format!("token {}", token))
-            .map_err(|_| GitHubError::InvalidHeader { name: header::AUTHORIZATION.as_str() })?;
-        headers.insert(header::AUTHORIZATION, auth_token);
         let accept = header::HeaderValue::from_str("application/vnd.github.v3+json")
             .map_err(|_| GitHubError::InvalidHeader { name: header::ACCEPT.as_str() })?;
         headers.insert(header::ACCEPT, accept);
@@ -39,65 +189,408 @@ impl GitHubClient {
         Ok(GitHubClient {
             client: Client::builder()
                 .default_headers(headers)
-                .build().map_err(|inner_error| GitHubError::HttpError { inner_error })?
+                .build().map_err(|inner_error| GitHubError::HttpError { inner_error })?,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            credentials,
+            cache: Mutex::new(HashMap::new()),
+            rate_limit: Mutex::new(RateLimit::default()),
         })
     }
 
-    pub fn get_last_commit<'a>(&self, repo: &'a RepoLocator)
-                               -> Result<Option<CommitLocator<'a>>> {
-        let commits_url = format!("{}/commits", &repo.url());
-        let mut response = self.client.get(&commits_url)
-            .send()
+    fn repo_url(&self, repo: &RepoLocator) -> String {
+        format!("{}/repos/{}/{}", &self.api_url, &repo.owner, &repo.repo)
+    }
+
+    pub fn rate_limit(&self) -> RateLimit {
+        *self.rate_limit.lock().unwrap()
+    }
+
+    /// Fails fast rather than spending requests that GitHub would only reject.
+    fn check_rate_limit(&self) -> Result<()> {
+        match self.rate_limit().resume_time() {
+            Some(resume_time) => {
+                let wait = resume_time.duration_since(SystemTime::now()).unwrap_or_default();
+                Err(GitHubError::RateLimited { seconds: wait.as_secs() + 1 })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The user name and password to give git, which are the same credentials used for the API.
+    pub fn git_credentials(&self) -> Result<(String, String)> {
+        match &self.credentials {
+            Credentials::Token { user, token } => Ok((user.to_string(), token.to_string())),
+            Credentials::App { .. } => Ok((APP_GIT_USER.to_string(), self.installation_token()?)),
+        }
+    }
+
+    fn authorization(&self) -> Result<header::HeaderValue> {
+        let token = match &self.credentials {
+            Credentials::Token { token, .. } => token.to_string(),
+            Credentials::App { .. } => self.installation_token()?,
+        };
+        header::HeaderValue::from_str(&format!("token {}", token))
+            .map_err(|_| GitHubError::InvalidHeader { name: header::AUTHORIZATION.as_str() })
+    }
+
+    /// Returns the current installation token of a GitHub App, first exchanging a freshly signed
+    /// app token for a new one if it is close to expiring.
+    fn installation_token(&self) -> Result<String> {
+        let (app_id, key, installation_id, installation_token) = match &self.credentials {
+            Credentials::App { app_id, key, installation_id, installation_token } =>
+                (*app_id, key, *installation_id, installation_token),
+            Credentials::Token { token, .. } => return Ok(token.to_string()),
+        };
+        let mut installation_token = installation_token.lock().unwrap();
+        if let Some(current) = installation_token.as_ref() {
+            if SystemTime::now() + TOKEN_REFRESH_MARGIN < current.expires_at {
+                return Ok(current.token.to_string());
+            }
+        }
+        let jwt = sign_jwt(app_id, key)?;
+        let bearer = header::HeaderValue::from_str(&format!("Bearer {}", jwt))
+            .map_err(|_| GitHubError::InvalidHeader { name: header::AUTHORIZATION.as_str() })?;
+        let access_tokens_url = format!("{}/app/installations/{}/access_tokens", &self.api_url, installation_id);
+        let response: InstallationTokenResponse = self.send_json(self.client.post(&access_tokens_url)
+            .header(header::AUTHORIZATION, bearer)
+            .header(header::ACCEPT, APPS_MEDIA_TYPE))?;
+        let expires_at = DateTime::parse_from_rfc3339(&response.expires_at)
+            .map_err(|_| GitHubError::InvalidExpiry { value: response.expires_at.to_string() })?;
+        let token = response.token;
+        *installation_token = Some(InstallationToken {
+            token: token.to_string(),
+            expires_at: UNIX_EPOCH + Duration::from_secs(expires_at.timestamp() as u64),
+        });
+        Ok(token)
+    }
+
+    fn execute(&self, mut request: Request) -> Result<Response> {
+        self.check_rate_limit()?;
+        if !request.headers().contains_key(header::AUTHORIZATION) {
+            let authorization = self.authorization()?;
+            request.headers_mut().insert(header::AUTHORIZATION, authorization);
+        }
+        let mut response = self.client.execute(request)
             .map_err(|inner_error| GitHubError::HttpError { inner_error })?;
-        let commits: CommitsResponse = response.json()
+        self.record_rate_limit(&response);
+        let status = response.status();
+        if status == StatusCode::FORBIDDEN || status == StatusCode::TOO_MANY_REQUESTS {
+            self.check_rate_limit()?;
+        }
+        if !status.is_success() && status != StatusCode::NOT_MODIFIED {
+            let message = response.json::<responses::ErrorResponse>()
+                .map(|error| error.message)
+                .unwrap_or_else(|_| status.canonical_reason().unwrap_or("Unknown error").to_string());
+            return Err(GitHubError::ApiError { status: status.as_u16(), message });
+        }
+        Ok(response)
+    }
+
+    fn send_json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let request = request.build()
             .map_err(|inner_error| GitHubError::HttpError { inner_error })?;
-        let last_commit = commits.first().map(|c| CommitLocator {
-            repo,
-            sha: c.sha.to_string(),
-        });
-        Ok(last_commit)
+        self.execute(request)?.json()
+            .map_err(|inner_error| GitHubError::HttpError { inner_error })
     }
 
-    pub fn get_statuses(&self, commit: &CommitLocator) -> Result<StatusesResponse> {
-        let statuses_url = format!("{}/statuses/{}", &commit.repo.url(), &commit.sha);
-        let mut response = self.client.get(&statuses_url)
-            .send()
+    fn record_rate_limit(&self, response: &Response) {
+        let header_number = |name: &str| response.headers().get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok());
+        let mut rate_limit = self.rate_limit.lock().unwrap();
+        if let Some(limit) = header_number("X-RateLimit-Limit") {
+            rate_limit.limit = Some(limit as u32);
+        }
+        if let Some(remaining) = header_number("X-RateLimit-Remaining") {
+            rate_limit.remaining = Some(remaining as u32);
+        }
+        if let Some(reset) = header_number("X-RateLimit-Reset") {
+            rate_limit.reset = Some(UNIX_EPOCH + Duration::from_secs(reset));
+        }
+        rate_limit.retry_after = header_number(header::RETRY_AFTER.as_str())
+            .map(|seconds| SystemTime::now() + Duration::from_secs(seconds));
+    }
+
+    /// Fetches and parses a JSON resource, asking GitHub to only send it again if it has changed
+    /// since it was last fetched.
+    fn get_json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let mut request = request
+            .build()
             .map_err(|inner_error| GitHubError::HttpError { inner_error })?;
-        let statuses: StatusesResponse = response.json()
+        let key = request.url().to_string();
+        if let Some(cached) = self.cache.lock().unwrap().get(&key) {
+            if let Ok(etag) = header::HeaderValue::from_str(&cached.etag) {
+                request.headers_mut().insert(header::IF_NONE_MATCH, etag);
+            }
+        }
+        let mut response = self.execute(request)?;
+        let mut cache = self.cache.lock().unwrap();
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(cached) = cache.get(&key) {
+                return serde_json::from_str(&cached.body)
+                    .map_err(|inner_error| GitHubError::InvalidResponse { inner_error });
+            }
+        }
+        let body = response.text()
             .map_err(|inner_error| GitHubError::HttpError { inner_error })?;
-        Ok(statuses)
+        let etag = response.headers().get(header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let parsed = serde_json::from_str(&body)
+            .map_err(|inner_error| GitHubError::InvalidResponse { inner_error })?;
+        if let Some(etag) = etag {
+            if cache.len() >= MAX_CACHED_RESPONSES {
+                cache.clear();
+            }
+            cache.insert(key, CachedResponse { etag, body });
+        }
+        Ok(parsed)
+    }
+
+    /// Finds the head commit of `branch`.
+    pub fn get_last_commit(&self, repo: &RepoLocator, branch: &str) -> Result<Option<CommitLocator>> {
+        Ok(self.get_commits(repo, branch, 1)?.into_iter().next())
+    }
+
+    /// Lists a page of commits on `branch`, newest first. Pages are numbered from 1 and an empty
+    /// page marks the end of the history.
+    pub fn get_commits(&self, repo: &RepoLocator, branch: &str, page: u32)
+                       -> Result<Vec<CommitLocator>> {
+        let commits_url = format!("{}/commits", &self.repo_url(repo));
+        let per_page = COMMITS_PER_PAGE.to_string();
+        let page = page.to_string();
+        let commits: CommitsResponse = self.get_json(self.client.get(&commits_url)
+            .query(&[("sha", branch), ("per_page", &per_page), ("page", &page)]))?;
+        let commits = commits.iter().map(|c| CommitLocator {
+            repo: repo.clone(),
+            sha: c.sha.to_string(),
+            pull_request: None,
+        }).collect();
+        Ok(commits)
+    }
+
+    /// Lists the head commit of every open pull request, including those opened from forks.
+    pub fn get_pull_request_heads(&self, repo: &RepoLocator) -> Result<Vec<CommitLocator>> {
+        let pulls_url = format!("{}/pulls", &self.repo_url(repo));
+        let per_page = PULL_REQUESTS_PER_PAGE.to_string();
+        let mut heads = vec![];
+        let mut page = 1;
+        loop {
+            let page_param = page.to_string();
+            let pull_requests: PullRequestsResponse = self.get_json(self.client.get(&pulls_url)
+                .query(&[("state", "open"), ("per_page", &per_page), ("page", &page_param)]))?;
+            if pull_requests.is_empty() {
+                break;
+            }
+            heads.extend(pull_requests.iter().map(|pr| CommitLocator {
+                repo: repo.clone(),
+                sha: pr.head.sha.to_string(),
+                pull_request: Some(PullRequestLocator {
+                    number: pr.number,
+                    base: pr.base.git_ref.to_string(),
+                }),
+            }));
+            page += 1;
+        }
+        Ok(heads)
+    }
+
+    pub fn get_statuses(&self, commit: &CommitLocator) -> Result<StatusesResponse> {
+        let statuses_url = format!("{}/statuses/{}", &self.repo_url(&commit.repo), &commit.sha);
+        self.get_json(self.client.get(&statuses_url))
+    }
+
+    /// Finds the most recent status posted for `context` on a commit.
+    pub fn get_status(&self, commit: &CommitLocator, context: &str) -> Result<Option<Status>> {
+        let statuses = self.get_statuses(commit)?;
+        let maybe_status = statuses.into_iter()
+            .find(|existing_status|
+                existing_status.context.as_ref().map_or(false, |c| c == context));
+        Ok(maybe_status)
+    }
+
+    /// Lists the statuses posted for `context` on a commit, newest first, back to and including
+    /// the most recent one that isn't pending.
+    pub fn get_statuses_since_result(&self, commit: &CommitLocator, context: &str) -> Result<StatusesResponse> {
+        let statuses_url = format!("{}/statuses/{}", &self.repo_url(&commit.repo), &commit.sha);
+        let per_page = STATUSES_PER_PAGE.to_string();
+        let mut found = vec![];
+        for page in 1..=MAX_STATUS_PAGES {
+            let page = page.to_string();
+            let statuses: StatusesResponse = self.get_json(self.client.get(&statuses_url)
+                .query(&[("per_page", &per_page), ("page", &page)]))?;
+            if statuses.is_empty() {
+                break;
+            }
+            for status in statuses {
+                if status.context.as_ref().map_or(true, |c| c != context) {
+                    continue;
+                }
+                let is_result = match status.state {
+                    State::Pending => false,
+                    _ => true,
+                };
+                found.push(status);
+                if is_result {
+                    return Ok(found);
+                }
+            }
+        }
+        Ok(found)
     }
 
     pub fn set_status(&self, commit: &CommitLocator, request: SetStatusRequest) -> Result<()> {
-        let statuses_url = format!("{}/statuses/{}", &commit.repo.url(), &commit.sha);
-        self.client.post(&statuses_url)
+        let statuses_url = format!("{}/statuses/{}", &self.repo_url(&commit.repo), &commit.sha);
+        let request = self.client.post(&statuses_url)
             .json(&request)
-            .send()
+            .build()
             .map_err(|inner_error| GitHubError::HttpError { inner_error })?;
+        self.execute(request)?;
+        Ok(())
+    }
+
+    /// Lists every check run called `name` on a commit, including those superseded by later ones.
+    pub fn get_check_runs(&self, commit: &CommitLocator, name: &str) -> Result<Vec<CheckRun>> {
+        let check_runs_url = format!("{}/commits/{}/check-runs", &self.repo_url(&commit.repo), &commit.sha);
+        let response: CheckRunsResponse = self.get_json(self.client.get(&check_runs_url)
+            .header(header::ACCEPT, CHECKS_MEDIA_TYPE)
+            .query(&[("check_name", name), ("filter", "all"), ("per_page", "100")]))?;
+        Ok(response.check_runs)
+    }
+
+    /// Finds the most recent check run called `name` on a commit.
+    pub fn get_check_run(&self, commit: &CommitLocator, name: &str) -> Result<Option<CheckRun>> {
+        let check_runs_url = format!("{}/commits/{}/check-runs", &self.repo_url(&commit.repo), &commit.sha);
+        let response: CheckRunsResponse = self.get_json(self.client.get(&check_runs_url)
+            .header(header::ACCEPT, CHECKS_MEDIA_TYPE)
+            .query(&[("check_name", name), ("filter", "latest")]))?;
+        Ok(response.check_runs.into_iter().next())
+    }
+
+    pub fn create_check_run(&self, commit: &CommitLocator, request: CreateCheckRunRequest) -> Result<CheckRun> {
+        let check_runs_url = format!("{}/check-runs", &self.repo_url(&commit.repo));
+        self.send_json(self.client.post(&check_runs_url)
+            .header(header::ACCEPT, CHECKS_MEDIA_TYPE)
+            .json(&request))
+    }
+
+    pub fn update_check_run(&self, commit: &CommitLocator, id: u64, request: UpdateCheckRunRequest)
+                            -> Result<CheckRun> {
+        let check_run_url = format!("{}/check-runs/{}", &self.repo_url(&commit.repo), id);
+        self.send_json(self.client.patch(&check_run_url)
+            .header(header::ACCEPT, CHECKS_MEDIA_TYPE)
+            .json(&request))
+    }
+
+    pub fn get_pull_request(&self, repo: &RepoLocator, number: u32) -> Result<CommitLocator> {
+        let pull_url = format!("{}/pulls/{}", &self.repo_url(repo), number);
+        let pull_request: PullRequest = self.get_json(self.client.get(&pull_url))?;
+        Ok(CommitLocator {
+            repo: repo.clone(),
+            sha: pull_request.head.sha,
+            pull_request: Some(PullRequestLocator {
+                number: pull_request.number,
+                base: pull_request.base.git_ref,
+            }),
+        })
+    }
+
+    /// Lists a page of the comments on the repository's issues and pull requests that were
+    /// posted or edited at or after `since`, oldest first.
+    pub fn get_issue_comments(&self, repo: &RepoLocator, since: &str, page: u32) -> Result<CommentsResponse> {
+        let comments_url = format!("{}/issues/comments", &self.repo_url(repo));
+        let per_page = COMMENTS_PER_PAGE.to_string();
+        let page = page.to_string();
+        self.get_json(self.client.get(&comments_url)
+            .query(&[("since", since), ("sort", "created"), ("direction", "asc"), ("per_page", &per_page),
+                     ("page", &page)]))
+    }
+
+    /// Lists a page of the comments on the repository's commits, oldest first.
+    pub fn get_commit_comments(&self, repo: &RepoLocator, page: u32) -> Result<CommentsResponse> {
+        let comments_url = format!("{}/comments", &self.repo_url(repo));
+        let per_page = COMMENTS_PER_PAGE.to_string();
+        let page = page.to_string();
+        self.get_json(self.client.get(&comments_url)
+            .query(&[("per_page", &per_page), ("page", &page)]))
+    }
+
+    /// Checks whether `user` can push to the repository.
+    pub fn can_write(&self, repo: &RepoLocator, user: &str) -> Result<bool> {
+        let permission_url = format!("{}/collaborators/{}/permission", &self.repo_url(repo), user);
+        let response: PermissionResponse = self.get_json(self.client.get(&permission_url))?;
+        Ok(match response.permission.as_str() {
+            "admin" | "maintain" | "write" => true,
+            _ => false,
+        })
+    }
+
+    /// Adds a reaction, such as `+1` or `eyes`, to an issue or commit comment.
+    pub fn react_to_comment(&self, repo: &RepoLocator, comment: &Comment, content: &str) -> Result<()> {
+        let kind = if comment.commit_id.is_some() { "comments" } else { "issues/comments" };
+        let reactions_url = format!("{}/{}/{}/reactions", &self.repo_url(repo), kind, comment.id);
+        let _: IgnoredAny = self.send_json(self.client.post(&reactions_url)
+            .header(header::ACCEPT, REACTIONS_MEDIA_TYPE)
+            .json(&ReactionRequest { content }))?;
         Ok(())
     }
 }
 
-#[derive(Debug)]
+/// Signs a JSON Web Token identifying the app, which is only good for asking for installation tokens.
+fn sign_jwt(app_id: u64, key: &PKey<Private>) -> Result<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let claims = JwtClaims {
+        iat: (now - JWT_BACKDATE).as_secs(),
+        exp: (now + JWT_LIFETIME).as_secs(),
+        iss: app_id,
+    };
+    let claims = serde_json::to_vec(&claims)
+        .map_err(|inner_error| GitHubError::InvalidResponse { inner_error })?;
+    let signing_input = format!("{}.{}",
+                                base64::encode_config(r#"{"alg":"RS256","typ":"JWT"}"#, base64::URL_SAFE_NO_PAD),
+                                base64::encode_config(&claims, base64::URL_SAFE_NO_PAD));
+    let signature = Signer::new(MessageDigest::sha256(), key)
+        .and_then(|mut signer| {
+            signer.update(signing_input.as_bytes())?;
+            signer.sign_to_vec()
+        })
+        .map_err(|inner_error| GitHubError::SigningError { inner_error })?;
+    Ok(format!("{}.{}", signing_input, base64::encode_config(&signature, base64::URL_SAFE_NO_PAD)))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct RepoLocator {
     pub owner: String,
     pub repo: String,
 }
 
-impl RepoLocator {
-    fn url(&self) -> String {
-        format!("{}/repos/{}/{}", BASE_URL, &self.owner, &self.repo)
+impl CommitLocator {
+    pub fn new(repo: &RepoLocator, sha: &str, pull_request: Option<PullRequestLocator>) -> Self {
+        CommitLocator {
+            repo: repo.clone(),
+            sha: sha.to_string(),
+            pull_request,
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct CommitLocator<'a> {
-    repo: &'a RepoLocator,
+#[derive(Clone, Debug)]
+pub struct CommitLocator {
+    repo: RepoLocator,
     pub sha: String,
+    /// The pull request this commit is the head of, if it was discovered through one.
+    pub pull_request: Option<PullRequestLocator>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PullRequestLocator {
+    pub number: u32,
+    /// The branch the pull request would be merged into.
+    pub base: String,
 }
 
 pub mod common {
-    #[derive(Serialize, Deserialize, Debug)]
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
     pub enum State {
         #[serde(rename = "error")]
         Error,
@@ -120,6 +613,59 @@ pub mod requests {
         pub description: Option<&'a str>,
         pub context: Option<&'a str>,
     }
+
+    #[derive(Serialize, Debug)]
+    pub struct ReactionRequest<'a> {
+        pub content: &'a str,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct JwtClaims {
+        pub iat: u64,
+        pub exp: u64,
+        pub iss: u64,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct CreateCheckRunRequest<'a> {
+        pub name: &'a str,
+        pub head_sha: &'a str,
+        /// Identifies the agent that created the check run.
+        pub external_id: Option<&'a str>,
+        pub status: &'a str,
+        pub details_url: Option<&'a str>,
+        pub output: Option<CheckRunOutput>,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct UpdateCheckRunRequest<'a> {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub status: Option<&'a str>,
+        /// Setting a conclusion also marks the check run as completed.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub conclusion: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub details_url: Option<&'a str>,
+        pub output: Option<CheckRunOutput>,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct CheckRunOutput {
+        pub title: String,
+        pub summary: String,
+        pub text: Option<String>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub annotations: Vec<Annotation>,
+    }
+
+    #[derive(Serialize, Clone, Debug)]
+    pub struct Annotation {
+        pub path: String,
+        pub start_line: u32,
+        pub end_line: u32,
+        pub annotation_level: &'static str,
+        pub message: String,
+    }
 }
 
 pub mod responses {
@@ -133,10 +679,84 @@ pub mod responses {
         pub html_url: String,
     }
 
+    pub type PullRequestsResponse = Vec<PullRequest>;
+
+    #[derive(Deserialize, Debug)]
+    pub struct PullRequest {
+        pub number: u32,
+        pub head: PullRequestHead,
+        pub base: PullRequestBase,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct PullRequestHead {
+        pub sha: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct PullRequestBase {
+        #[serde(rename = "ref")]
+        pub git_ref: String,
+    }
+
+    pub type CommentsResponse = Vec<Comment>;
+
+    /// A comment on an issue, pull request or commit.
+    #[derive(Deserialize, Debug)]
+    pub struct Comment {
+        /// Increases with each comment posted.
+        pub id: u64,
+        pub body: String,
+        pub user: User,
+        pub html_url: String,
+        pub created_at: String,
+        pub updated_at: String,
+        /// The commit commented on, which only commit comments have.
+        pub commit_id: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct User {
+        pub login: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct PermissionResponse {
+        pub permission: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct ErrorResponse {
+        pub message: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct InstallationTokenResponse {
+        pub token: String,
+        pub expires_at: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct CheckRunsResponse {
+        pub check_runs: Vec<CheckRun>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct CheckRun {
+        pub id: u64,
+        pub external_id: Option<String>,
+        pub status: String,
+        pub conclusion: Option<String>,
+        pub started_at: Option<String>,
+    }
+
     pub type StatusesResponse = Vec<Status>;
 
     #[derive(Serialize, Deserialize, Debug)]
     pub struct Status {
+        /// Increases with each status posted, so orders statuses more finely than `created_at`.
+        pub id: u64,
+        pub created_at: String,
         pub state: State,
         pub target_url: Option<String>,
         pub description: Option<String>,
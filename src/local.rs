@@ -1,40 +1,371 @@
 use crate::hub::RepoLocator;
 use failure::Error;
+use failure::format_err;
 use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::Mutex;
 use crate::hub::CommitLocator;
+use crate::hub::PullRequestLocator;
+use git2::Commit;
+use git2::Cred;
+use git2::build::CheckoutBuilder;
+use git2::CredentialType;
+use git2::ErrorClass;
+use git2::FetchOptions;
 use git2::Oid;
+use git2::RemoteCallbacks;
 use git2::Repository;
 use git2::ResetType;
+use git2::Signature;
+use git2::StatusOptions;
+use git2::SubmoduleUpdateOptions;
 
+const INDEX_STAGE_SHIFT: u16 = 12;
+const INDEX_STAGE_MASK: u16 = 0x3;
+
+/// How git authenticates with the server. Credentials are handed over when asked for, rather
+/// than written into the remote URL, so they are never saved in the working copy.
+pub enum GitCredentials {
+    /// A user name and token, given as HTTPS basic authentication.
+    Token { user: String, token: String },
+    /// An SSH key from `key_file`, or from the running ssh-agent.
+    Ssh { key_file: Option<PathBuf> },
+}
+
+#[derive(Fail, Debug)]
+pub enum LocalRepoError {
+    #[fail(display = "Merge conflict with {} in {}", base, paths)]
+    MergeConflict {
+        base: String,
+        /// The conflicting files, separated by commas.
+        paths: String,
+    },
+
+    #[fail(display = "Can't merge into {}, as they share no history in the last {} commits fetched", base, depth)]
+    HistoryTooShallow {
+        base: String,
+        depth: u32,
+    },
+}
+
+/// What to delete from a working copy before each build.
+#[derive(Clone, Debug)]
+pub enum CleanPolicy {
+    /// Leave untracked and ignored files alone, so builds can be incremental.
+    None,
+    /// Delete every untracked and ignored file, like `git clean -fdx`.
+    All,
+    /// Delete every untracked and ignored file except those under these directories, which are
+    /// relative to the root of the working copy.
+    Keep(Vec<String>),
+}
+
+/// A working copy of a repository, kept under the workspace between runs.
+///
+/// Commits are fetched into a bare repository shared by every working copy of the same repository,
+/// whatever their branch, context or worker, and working copies borrow its objects as alternates.
+/// Each commit is then only downloaded once, and a new working copy costs nothing but a checkout.
+///
+/// The libgit2 bundled with git2 0.8 can't make shallow fetches, so when the history is limited to
+/// a depth the fetch is made with the git command line instead. Partial fetches aren't offered, as
+/// libgit2 couldn't fetch the missing objects when checking out.
 pub struct LocalRepo {
     path: String,
+    url: String,
+    /// Where repositories are cloned from, with a trailing slash. Tokens are only given to URLs
+    /// under it.
+    git_url: String,
+    cache_path: PathBuf,
     default_branch: String,
+    clean: CleanPolicy,
+    depth: Option<u32>,
     git: Repository,
 }
 
 impl LocalRepo {
-    pub fn new(user: &str, token: &str, locator: &RepoLocator, branch: &str, context: &str) -> Result<Self, Error> {
-        let url = format!("https://{}:{}@github.com/{}/{}.git", &user, &token, &locator.owner, &locator.repo);
-        let path = format!("/tmp/crane/{}/{}/{}", &locator.owner, &locator.repo, &context);
-        fs::remove_dir_all(&path).unwrap_or(());
-        fs::create_dir_all(&path)?;
+    /// Opens the working copy for one worker under `workspace`, creating it if it doesn't exist or
+    /// was cloned from elsewhere. Each worker gets its own copy, so builds of the same target can
+    /// run side by side.
+    pub fn new(workspace: &Path, git_url: &str, locator: &RepoLocator, branch: &str, context: &str,
+               worker: usize, clean: CleanPolicy, depth: Option<u32>) -> Result<Self, Error> {
+        let git_url = format!("{}/", git_url.trim_end_matches('/'));
+        let url = format!("{}{}/{}.git", &git_url, &locator.owner, &locator.repo);
+        let cache_path = workspace.join("objects").join(&locator.owner).join(format!("{}.git", &locator.repo));
+        let path = workspace.join("checkouts").join(&locator.owner).join(&locator.repo).join(branch).join(context)
+            .join(worker.to_string());
+        let git = match open_with_origin(&path, &url) {
+            Some(git) => git,
+            None => {
+                fs::remove_dir_all(&path).unwrap_or(());
+                fs::create_dir_all(&path)?;
+                let git = Repository::init(&path)?;
+                git.remote("origin", &url)?;
+                git
+            }
+        };
+        let alternates = git.path().join("objects").join("info").join("alternates");
+        fs::create_dir_all(alternates.parent().unwrap())?;
+        fs::write(&alternates, format!("{}\n", cache_path.join("objects").display()))?;
         let repo = LocalRepo {
-            path: path.clone(),
+            path: path.to_string_lossy().to_string(),
+            url,
+            git_url,
+            cache_path,
             default_branch: branch.to_string(),
-            git: Repository::clone(&url, &path)?,
+            clean,
+            depth,
+            git,
         };
         Ok(repo)
     }
 
-    pub fn reset_to(&mut self, commit: &CommitLocator) -> Result<(), Error> {
-        self.git.find_remote("origin")?
-            .fetch(&[&self.default_branch], None, None)?;
-        let git_commit = self.git.find_commit(Oid::from_str(&commit.sha)?)?;
-        self.git.reset(&git_commit.as_object(), ResetType::Hard, None)?;
+    /// Fetches and checks out `commit`, cleans the working copy and brings its submodules up to
+    /// date. The head of a pull request is first merged into the latest commit on its base
+    /// branch, failing with `LocalRepoError::MergeConflict` if they conflict.
+    ///
+    /// The credentials are given afresh each time, as tokens expire. Fetches into the shared
+    /// repository are made one at a time under `cache_lock`.
+    pub fn reset_to(&mut self, commit: &CommitLocator, credentials: &GitCredentials,
+                    cache_lock: &Mutex<()>) -> Result<(), Error> {
+        // Pull request heads, including those from forks, are mirrored under refs/pull on the base repository.
+        let mut refspecs = vec![];
+        let base_ref = match &commit.pull_request {
+            Some(pull_request) => {
+                refspecs.push(format!("+refs/pull/{}/head:refs/pull/{}/head", pull_request.number, pull_request.number));
+                Some(branch_refspec(&pull_request.base, &mut refspecs))
+            }
+            None => {
+                branch_refspec(&self.default_branch, &mut refspecs);
+                None
+            }
+        };
+        let oid = Oid::from_str(&commit.sha)?;
+        // The base branch is fetched every time, as pull requests are tested against its latest commit.
+        let mut base = None;
+        if self.git.find_commit(oid).is_err() || base_ref.is_some() {
+            let _guard = cache_lock.lock().unwrap();
+            let cache = self.open_cache()?;
+            match self.depth {
+                Some(depth) => {
+                    // The commit may be further back than the depth from the head of its branch.
+                    refspecs.push(commit.sha.to_string());
+                    shallow_fetch(&self.cache_path, depth, &refspecs, credentials)?;
+                }
+                None => {
+                    cache.find_remote("origin")?
+                        .fetch(&refspecs.iter().map(String::as_str).collect::<Vec<_>>(),
+                               Some(&mut fetch_options(credentials, &self.git_url)), None)?;
+                }
+            }
+            if let Some(base_ref) = &base_ref {
+                base = Some(cache.refname_to_id(base_ref)?);
+            }
+            // Reopened so that libgit2 notices the new packs in the alternate object directory.
+            self.git = Repository::open(&self.path)?;
+        }
+        let git_commit = self.git.find_commit(oid)?;
+        let target = match (base, &commit.pull_request) {
+            (Some(base), Some(pull_request)) => {
+                let base_commit = self.git.find_commit(base)?;
+                self.merge(&base_commit, &git_commit, pull_request)?
+            }
+            _ => oid,
+        };
+        let target = self.git.find_object(target, None)?;
+        self.git.reset(&target, ResetType::Hard, None)?;
+        self.clean_working_copy()?;
+        update_submodules(&self.git, credentials, &self.git_url)?;
         Ok(())
     }
 
+    /// Creates a merge commit of `head` into `base`, without moving any branch to it.
+    fn merge(&self, base: &Commit, head: &Commit, pull_request: &PullRequestLocator) -> Result<Oid, Error> {
+        // libgit2 can't tell a shallow history from a broken one, so looking for the merge base
+        // fails with a missing object when it is further back than was fetched.
+        let mut index = match (self.git.merge_commits(base, head, None), self.depth) {
+            (Err(ref e), Some(depth)) if e.class() == ErrorClass::Odb =>
+                return Err(LocalRepoError::HistoryTooShallow {
+                    base: pull_request.base.to_string(),
+                    depth,
+                }.into()),
+            (result, _) => result?,
+        };
+        if index.has_conflicts() {
+            // Conflicting files are left in the index at a non-zero stage, one entry for each side.
+            let mut paths: Vec<String> = index.iter()
+                .filter(|entry| (entry.flags >> INDEX_STAGE_SHIFT) & INDEX_STAGE_MASK != 0)
+                .map(|entry| String::from_utf8_lossy(&entry.path).to_string())
+                .collect();
+            paths.dedup();
+            return Err(LocalRepoError::MergeConflict {
+                base: pull_request.base.to_string(),
+                paths: paths.join(", "),
+            }.into());
+        }
+        let tree = self.git.find_tree(index.write_tree_to(&self.git)?)?;
+        let signature = Signature::now("crane", "crane@localhost")?;
+        let message = format!("Merge pull request #{} into {}", pull_request.number, &pull_request.base);
+        Ok(self.git.commit(None, &signature, &signature, &message, &tree, &[base, head])?)
+    }
+
+    fn clean_working_copy(&self) -> Result<(), Error> {
+        let keep = match &self.clean {
+            CleanPolicy::None => return Ok(()),
+            CleanPolicy::All => vec![],
+            CleanPolicy::Keep(keep) => keep.clone(),
+        };
+        let mut options = StatusOptions::new();
+        options.include_untracked(true)
+            .include_ignored(true)
+            .recurse_untracked_dirs(false)
+            .recurse_ignored_dirs(false)
+            .exclude_submodules(true);
+        let untracked: Vec<String> = self.git.statuses(Some(&mut options))?.iter()
+            .filter(|entry| entry.status().is_wt_new() || entry.status().is_ignored())
+            .filter_map(|entry| entry.path().map(|path| path.trim_end_matches('/').to_string()))
+            .collect();
+        for path in untracked {
+            remove_except(Path::new(&self.path), &path, &keep)?;
+        }
+        Ok(())
+    }
+
+    fn open_cache(&self) -> Result<Repository, Error> {
+        if let Some(cache) = open_with_origin(&self.cache_path, &self.url) {
+            return Ok(cache);
+        }
+        fs::create_dir_all(&self.cache_path)?;
+        let cache = Repository::init_bare(&self.cache_path)?;
+        if cache.find_remote("origin").is_ok() {
+            cache.remote_set_url("origin", &self.url)?;
+        } else {
+            cache.remote("origin", &self.url)?;
+        }
+        Ok(cache)
+    }
+
     pub fn path(&self) -> &str {
         &self.path
     }
 }
+
+/// Adds a refspec fetching `branch` to `refspecs`, returning the ref it is fetched into.
+fn branch_refspec(branch: &str, refspecs: &mut Vec<String>) -> String {
+    let remote_ref = format!("refs/remotes/origin/{}", branch);
+    refspecs.push(format!("+refs/heads/{}:{}", branch, &remote_ref));
+    remote_ref
+}
+
+/// Removes `relative` from the working copy at `root`, apart from any of the `keep` directories
+/// within it.
+fn remove_except(root: &Path, relative: &str, keep: &[String]) -> Result<(), Error> {
+    if keep.iter().any(|kept| relative == kept || relative.starts_with(&format!("{}/", kept))) {
+        return Ok(());
+    }
+    let path = root.join(relative);
+    let metadata = match fs::symlink_metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+    if !metadata.is_dir() {
+        fs::remove_file(&path)?;
+    } else if keep.iter().any(|kept| kept.starts_with(&format!("{}/", relative))) {
+        for entry in fs::read_dir(&path)? {
+            let name = entry?.file_name();
+            remove_except(root, &format!("{}/{}", relative, name.to_string_lossy()), keep)?;
+        }
+    } else {
+        fs::remove_dir_all(&path)?;
+    }
+    Ok(())
+}
+
+/// Checks out the commit recorded for each submodule, cloning it first if need be, and then does
+/// the same for the submodules of each submodule.
+fn update_submodules(git: &Repository, credentials: &GitCredentials, git_url: &str) -> Result<(), Error> {
+    for mut submodule in git.submodules()? {
+        let mut checkout = CheckoutBuilder::new();
+        checkout.force();
+        let mut options = SubmoduleUpdateOptions::new();
+        options.fetch(fetch_options(credentials, git_url))
+            .checkout(checkout);
+        submodule.update(true, Some(&mut options))?;
+        update_submodules(&submodule.open()?, credentials, git_url)?;
+    }
+    Ok(())
+}
+
+/// Opens the repository at `path` if there is one and it was cloned from `url`.
+fn open_with_origin(path: &Path, url: &str) -> Option<Repository> {
+    let git = Repository::open(path).ok()?;
+    let matches = git.find_remote("origin").ok()?.url() == Some(url);
+    if matches { Some(git) } else { None }
+}
+
+/// Fetches `refspecs` into the repository at `path` with the git command line, keeping no more
+/// than `depth` commits of history.
+fn shallow_fetch(path: &Path, depth: u32, refspecs: &[String], credentials: &GitCredentials) -> Result<(), Error> {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(path)
+        .arg("fetch")
+        .arg("--no-tags")
+        .arg(format!("--depth={}", depth))
+        .arg("origin")
+        .args(refspecs)
+        .stdin(Stdio::null())
+        .env("GIT_TERMINAL_PROMPT", "0");
+    // Credentials go in the environment rather than on the command line, where anyone could see them.
+    match credentials {
+        GitCredentials::Token { user, token } => {
+            let basic = base64::encode(&format!("{}:{}", user, token));
+            command.env("GIT_CONFIG_COUNT", "1")
+                .env("GIT_CONFIG_KEY_0", "http.extraHeader")
+                .env("GIT_CONFIG_VALUE_0", format!("Authorization: Basic {}", basic));
+        }
+        GitCredentials::Ssh { key_file: Some(key_file) } => {
+            command.env("GIT_SSH_COMMAND", format!("ssh -i '{}' -o IdentitiesOnly=yes", key_file.display()));
+        }
+        GitCredentials::Ssh { key_file: None } => {}
+    }
+    let output = command.output()
+        .map_err(|e| format_err!("Could not run git to make a shallow fetch: {}", e))?;
+    if !output.status.success() {
+        return Err(format_err!("git fetch failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+/// Options that authenticate with `credentials`. Submodules can be cloned from anywhere, so a token
+/// is only handed to URLs under `git_url`, and other servers are refused it.
+fn fetch_options<'a>(credentials: &'a GitCredentials, git_url: &'a str) -> FetchOptions<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    // libgit2 keeps asking for as long as the server rejects what it is given, so only try once.
+    let mut attempted = false;
+    callbacks.credentials(move |url, username_from_url, allowed_types| {
+        let user = username_from_url.unwrap_or("git");
+        if allowed_types.contains(CredentialType::USERNAME) {
+            return Cred::username(user);
+        }
+        if attempted {
+            return Err(git2::Error::from_str("The git server rejected the credentials"));
+        }
+        attempted = true;
+        match credentials {
+            GitCredentials::Token { .. } if !url.starts_with(git_url) =>
+                Err(git2::Error::from_str(&format!("Not sending the token to {}, which isn't under {}", url, git_url))),
+            GitCredentials::Token { user, token } if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) =>
+                Cred::userpass_plaintext(user, token),
+            GitCredentials::Ssh { key_file: Some(key_file) } if allowed_types.contains(CredentialType::SSH_KEY) =>
+                Cred::ssh_key(user, None, key_file, None),
+            GitCredentials::Ssh { key_file: None } if allowed_types.contains(CredentialType::SSH_KEY) =>
+                Cred::ssh_key_from_agent(user),
+            _ => Err(git2::Error::from_str("The git server asked for an unsupported kind of credentials")),
+        }
+    });
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    options
+}
@@ -1,9 +1,16 @@
+extern crate base64;
+extern crate chrono;
 extern crate clap;
 extern crate ctrlc;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+extern crate futures;
 extern crate git2;
+extern crate hex;
+extern crate hmac;
+extern crate libc;
+extern crate openssl;
 extern crate rand;
 extern crate reqwest;
 extern crate rusoto_core;
@@ -12,31 +19,60 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate sha1;
 extern crate termion;
+extern crate tiny_http;
+extern crate toml;
 extern crate tui;
 
 mod args;
+mod build;
+mod checks;
+mod claim;
+mod comments;
 mod timer;
+mod history;
 mod hub;
 mod local;
 mod s3;
+mod server;
+mod store;
+mod target;
 mod ui;
+mod webhook;
+mod worker;
 
 use crate::args::parse_args;
+use crate::comments::CommentCursor;
+use crate::comments::Subject;
+use crate::history::BuildHistory;
+use crate::hub::CommitLocator;
+use crate::hub::common::State;
+use crate::hub::responses::Comment;
 use crate::hub::GitHubClient;
+use crate::hub::GitHubError;
+use crate::hub::PullRequestLocator;
 use crate::hub::RepoLocator;
 use crate::timer::RandomExpBackoffTimer;
-use crate::hub::requests::SetStatusRequest;
-use crate::hub::common::State;
-use crate::local::LocalRepo;
-use crate::s3::Bucket;
+use crate::target::Target;
+use crate::ui::Command;
 use crate::ui::Property;
 use crate::ui::Summary;
+use crate::webhook::WebhookEvent;
+use crate::worker::Event;
+use crate::worker::Job;
+use crate::worker::WorkerPool;
+use crate::worker::WorkerSettings;
 use failure::Error;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
-use std::process::Command;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
 use std::thread;
 use std::io;
 use std::io::Write;
@@ -45,61 +81,309 @@ use std::panic;
 use termion::input::TermRead;
 use termion::event::Key;
 use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 use std::panic::PanicInfo;
 
 const TICK_PERIOD: Duration = Duration::from_millis(64);
+const MAX_UNBUILT_COMMITS: usize = 100;
+const RECONCILE_MIN_MILLIS: u64 = 60_000;
+const RECONCILE_SCALE_MILLIS: u64 = 60_000;
+const RECONCILE_MAX_MILLIS: u64 = 300_000;
+/// How often to look for builds left pending by agents that stopped.
+const ORPHAN_SCAN_PERIOD: Duration = Duration::from_secs(10 * 60);
+/// How many dispatched commits to remember, so they can be rebuilt from the terminal.
+const MAX_RECENT_COMMITS: usize = 100;
 
 fn main() -> Result<(), Error> {
     set_up_panic_handler();
 
-    let args = parse_args();
+    let args = parse_args()?;
 
     let properties = vec![
-        Property::new("Owner", &args.owner),
-        Property::new("Repo", &args.repository),
-        Property::new("Branch", &args.branch),
-        Property::new("Build Type", &args.context),
+        Property::new("Targets", &args.targets.len().to_string()),
+        Property::new("All commits", if args.all_commits { "Yes" } else { "No" }),
+        Property::new("Pull requests", if args.pull_requests { "Yes" } else { "No" }),
+        Property::new("Reporting", if args.checks { "Checks" } else { "Statuses" }),
+        Property::new("Jobs", &args.jobs.to_string()),
+        Property::new("API quota", "Unknown"),
+        Property::new("Polling", "Running"),
     ];
 
-    let mut ui = Summary::new(properties)?;
-
-    let repo = RepoLocator {
-        owner: args.owner,
-        repo: args.repository,
+    let github = Arc::new(GitHubClient::new(&args.auth, &args.api_url)?);
+    let public_url = match (args.http_port, &args.public_url) {
+        (Some(_), Some(public_url)) => Some(public_url.to_string()),
+        (Some(port), None) => Some(format!("http://{}:{}", hostname(), port)),
+        (None, _) => None,
     };
+    let store = &args.store;
+    let workspace = PathBuf::from(&args.workspace);
+    let targets: Vec<Arc<Target>> = args.targets.into_iter()
+        .map(|target_args| Arc::new(Target::new(target_args, store, public_url.as_ref().map(String::as_str),
+                                                &workspace)))
+        .collect();
+    if let Some(port) = args.http_port {
+        server::serve(port, targets.clone())?;
+    }
+    let mut queues: Vec<VecDeque<CommitLocator>> = targets.iter().map(|_| VecDeque::new()).collect();
+    // Commits being built, with the flags that cancel their builds.
+    let mut in_flight: HashMap<(usize, String), Arc<AtomicBool>> = HashMap::new();
+    let mut recent_commits: VecDeque<(usize, CommitLocator)> = VecDeque::new();
+    let mut forced = HashSet::new();
+    let mut comment_cursors: HashMap<RepoLocator, CommentCursor> = HashMap::new();
+    if args.retry_command.is_some() {
+        for target in &targets {
+            comment_cursors.entry(target.repo.clone()).or_insert_with(CommentCursor::new);
+        }
+    }
+    // Comments can arrive both by webhook and by polling, but should only be acted on once.
+    let mut handled_comments = HashSet::new();
+
+    let target_labels = targets.iter().map(|target| target.label.to_string()).collect();
+    let mut ui = Summary::new(properties, target_labels)?;
+
+    // Pick up where the last run left off, so earlier builds can still be seen and rebuilt.
+    let mut history = BuildHistory::open(&workspace)?;
+    for record in history.load(MAX_RECENT_COMMITS)? {
+        let index = match targets.iter().position(|target| target.label == record.target) {
+            Some(index) => index,
+            None => continue,
+        };
+        let status = match record.state {
+            State::Success => ui::Status::Succeeded,
+            State::Failure | State::Error => ui::Status::Failed,
+            State::Pending => ui::Status::Pending,
+        };
+        ui.record_build(&record.target, &record.sha, status);
+        let commit = CommitLocator::new(&targets[index].repo, &record.sha, record.pull_request);
+        remember_commit(&mut recent_commits, index, commit);
+    }
 
+    let (running, commands) = monitor_application_state();
+    let is_running = || running.load(Ordering::SeqCst);
+    let workers = WorkerPool::new(args.jobs, github.clone(), WorkerSettings {
+        agent_id: claim::load_agent_id(&workspace)?,
+        orphan_age: args.orphan_age,
+        orphans: args.orphans,
+        label: hostname(),
+        git_url: args.git_url,
+        ssh_key_file: args.ssh_key_file.map(PathBuf::from),
+        workspace: workspace.clone(),
+        clean: args.clean,
+        fetch_depth: args.fetch_depth,
+        timeout: args.timeout,
+        checks: args.checks,
+    }, running.clone());
 
-    let github = GitHubClient::new(&args.token)?;
-    let mut local = LocalRepo::new(&args.user, &args.token, &repo, &args.branch, &args.context)?;
-    let mut timer = RandomExpBackoffTimer::new();
-    let bucket_key_prefix = format!("build/logs/{}/{}", &args.branch, &args.context);
-    let bucket = Bucket::new(args.region, args.bucket, bucket_key_prefix);
-    let is_running = monitor_application_state();
+    // With webhooks announcing new commits, polling only needs to catch the odd missed event.
+    let (webhook_events, mut timer) = match (args.webhook_port, args.webhook_secret) {
+        (Some(port), Some(secret)) => {
+            let (sender, receiver) = mpsc::channel();
+            webhook::listen(port, secret, sender)?;
+            let timer = RandomExpBackoffTimer::with_range(RECONCILE_MIN_MILLIS, RECONCILE_SCALE_MILLIS,
+                                                          RECONCILE_MAX_MILLIS);
+            (Some(receiver), timer)
+        }
+        _ => (None, RandomExpBackoffTimer::new()),
+    };
+    let mut next_target = 0;
+    let mut shown_build = None;
+    let mut next_orphan_scan = Instant::now();
+    let mut paused = false;
+    let mut poll_now = false;
     while is_running() {
-        if timer.is_due() {
-            test_latest_commit(&github, &mut local, &repo,
-                               &bucket, &mut ui, &args.context,
-                               &args.script).unwrap_or_else(|e| {
-                ui.record_error(e);
-            });
-            let due_time = timer.reset();
+        while let Ok(command) = commands.try_recv() {
+            match command {
+                Command::SelectNewer | Command::SelectOlder => ui.select_build(&command),
+                Command::Rebuild => {
+                    let recent_commit = selected_job(&ui, &targets)
+                        .and_then(|(index, sha)| recent_commits.iter()
+                            .find(|(recent_index, commit)| *recent_index == index && commit.sha == sha));
+                    if let Some((index, commit)) = recent_commit {
+                        enqueue(&mut queues[*index], commit.clone());
+                        forced.insert((*index, commit.sha.to_string()));
+                    }
+                }
+                Command::Cancel => {
+                    if let Some(cancelled) = selected_job(&ui, &targets).and_then(|job| in_flight.get(&job)) {
+                        cancelled.store(true, Ordering::SeqCst);
+                    }
+                }
+                Command::TogglePolling => {
+                    paused = !paused;
+                    ui.set_property("Polling", if paused { "Paused" } else { "Running" });
+                }
+                Command::PollNow => poll_now = true,
+            }
+        }
+
+        if poll_now || (!paused && timer.is_due()) {
+            poll_now = false;
+            for (target, queue) in targets.iter().zip(queues.iter_mut()) {
+                queue_commits(&github, target, queue, args.all_commits, args.pull_requests, args.checks)
+                    .unwrap_or_else(|e| ui.record_error(e));
+            }
+            if let Some(command) = &args.retry_command {
+                for (repo, cursor) in comment_cursors.iter_mut() {
+                    let (comments, next_cursor) = match cursor.poll(&github, repo) {
+                        Ok(polled) => polled,
+                        Err(e) => {
+                            ui.record_error(e);
+                            continue;
+                        }
+                    };
+                    // The cursor only moves on once every comment has been dealt with, so any that
+                    // couldn't be are seen again next time.
+                    let mut all_handled = true;
+                    let polled: Vec<String> = comments.iter().map(|comment| comment.html_url.to_string()).collect();
+                    for comment in comments {
+                        if handled_comments.contains(&comment.html_url) {
+                            continue;
+                        }
+                        match retry_from_comment(&github, &targets, &mut queues, &mut forced, repo, &comment, command,
+                                                 args.pull_requests) {
+                            Ok(()) => {
+                                handled_comments.insert(comment.html_url);
+                            }
+                            Err(e) => {
+                                if is_client_error(&e) {
+                                    handled_comments.insert(comment.html_url);
+                                } else {
+                                    all_handled = false;
+                                }
+                                ui.record_error(e);
+                            }
+                        }
+                    }
+                    if all_handled {
+                        *cursor = next_cursor;
+                        // Polling won't return these again, so they needn't be remembered any longer.
+                        for html_url in polled {
+                            handled_comments.remove(&html_url);
+                        }
+                    }
+                }
+            }
+            let mut due_time = timer.reset();
+            if let Some(resume_time) = github.rate_limit().resume_time() {
+                let wait = resume_time.duration_since(SystemTime::now()).unwrap_or_default();
+                due_time = timer.delay_until(Instant::now() + wait);
+            }
             ui.reset_retry_window(due_time);
         }
 
+        if Instant::now() >= next_orphan_scan && github.rate_limit().resume_time().is_none() {
+            for (index, (target, queue)) in targets.iter().zip(queues.iter_mut()).enumerate() {
+                queue_orphans(&github, target, queue, args.pull_requests, args.checks,
+                              |sha| in_flight.contains_key(&(index, sha.to_string())))
+                    .unwrap_or_else(|e| ui.record_error(e));
+            }
+            next_orphan_scan = Instant::now() + ORPHAN_SCAN_PERIOD;
+        }
+
+        if let Some(webhook_events) = &webhook_events {
+            while let Ok(event) = webhook_events.try_recv() {
+                match (event, &args.retry_command) {
+                    (WebhookEvent::Comment { owner, repo, comment }, Some(command)) => {
+                        let repo = RepoLocator { owner, repo };
+                        // Comments are only remembered until polling passes them, so those on
+                        // repositories that aren't polled are ignored.
+                        if comment_cursors.contains_key(&repo) && !handled_comments.contains(&comment.html_url) {
+                            // A comment that can't be dealt with now is picked up again by polling.
+                            match retry_from_comment(&github, &targets, &mut queues, &mut forced, &repo, &comment,
+                                                     command, args.pull_requests) {
+                                Ok(()) => {
+                                    handled_comments.insert(comment.html_url);
+                                }
+                                Err(e) => {
+                                    if is_client_error(&e) {
+                                        handled_comments.insert(comment.html_url);
+                                    }
+                                    ui.record_error(e);
+                                }
+                            }
+                        }
+                    }
+                    (event, _) => queue_webhook_event(event, &targets, &mut queues, args.all_commits,
+                                                      args.pull_requests),
+                }
+            }
+        }
+
+        // Take turns between targets so a busy repository can't starve the others. Builds are held
+        // back while the API quota is exhausted, as they couldn't report their results anyway.
+        let rate_limit = github.rate_limit();
+        ui.set_property("API quota", &rate_limit.to_string());
+        while in_flight.len() < workers.size() && rate_limit.resume_time().is_none() {
+            let target_count = targets.len();
+            let ready_target = (0..target_count)
+                .map(|offset| (next_target + offset) % target_count)
+                .find(|&index| queues[index].iter()
+                    .any(|commit| !in_flight.contains_key(&(index, commit.sha.to_string()))));
+            let index = match ready_target {
+                Some(index) => index,
+                None => break,
+            };
+            next_target = (index + 1) % target_count;
+            let position = queues[index].iter()
+                .position(|commit| !in_flight.contains_key(&(index, commit.sha.to_string())))
+                .unwrap();
+            let commit = queues[index].remove(position).unwrap();
+            let key = (index, commit.sha.to_string());
+            let cancelled = Arc::new(AtomicBool::new(false));
+            in_flight.insert(key.clone(), cancelled.clone());
+            remember_commit(&mut recent_commits, index, commit.clone());
+            workers.submit(Job {
+                target_index: index,
+                target: targets[index].clone(),
+                commit,
+                force: forced.remove(&key),
+                cancelled,
+            })?;
+        }
+
+        while let Some(event) = workers.try_recv() {
+            match event {
+                Event::Started { target_index, sha } => {
+                    shown_build = Some((target_index, sha));
+                }
+                Event::Status { target_index, sha, status } => {
+                    ui.record_build(&targets[target_index].label, &sha, status);
+                }
+                Event::Output { target_index, sha, lines } => {
+                    if shown_build == Some((target_index, sha)) {
+                        ui.record_output(lines);
+                    }
+                }
+                Event::Error { error } => ui.record_error(error),
+                Event::Finished { record } => {
+                    history.append(&record).unwrap_or_else(|e| ui.record_error(e));
+                }
+                Event::Done { target_index, sha, result } => {
+                    in_flight.remove(&(target_index, sha));
+                    result.unwrap_or_else(|e| ui.record_error(e));
+                }
+            }
+        }
+
         ui.render()?;
         thread::sleep(TICK_PERIOD);
     }
 
+    workers.shutdown();
     Ok(())
 }
 
-fn monitor_application_state() -> impl Fn() -> bool {
+/// Watches for the signals and keys that stop the application, and passes any other commands typed
+/// at the terminal back to the main loop.
+fn monitor_application_state() -> (Arc<AtomicBool>, Receiver<Command>) {
     let running = Arc::new(AtomicBool::new(true));
     let sig_int_running = running.clone();
     ctrlc::set_handler(move || {
         sig_int_running.store(false, Ordering::SeqCst);
     }).expect("Error setting Ctrl-C handler");
     let ctrl_c_running = running.clone();
+    let (sender, receiver) = mpsc::channel();
     thread::spawn(move || {
        let input = io::stdin();
        for event in input.keys() {
@@ -107,66 +391,196 @@ fn monitor_application_state() -> impl Fn() -> bool {
                match key {
                    Key::Ctrl('c') | Key::Char('q') =>
                        ctrl_c_running.store(false, Ordering::SeqCst),
+                   Key::Up => sender.send(Command::SelectNewer).unwrap_or(()),
+                   Key::Down => sender.send(Command::SelectOlder).unwrap_or(()),
+                   Key::Char('r') => sender.send(Command::Rebuild).unwrap_or(()),
+                   Key::Char('c') => sender.send(Command::Cancel).unwrap_or(()),
+                   Key::Char('p') => sender.send(Command::TogglePolling).unwrap_or(()),
+                   Key::Char('n') => sender.send(Command::PollNow).unwrap_or(()),
                    _ => {}
                }
            }
        }
     });
-    return move || running.load(Ordering::SeqCst);
+    (running, receiver)
 }
 
-fn test_latest_commit(github: &GitHubClient, local: &mut LocalRepo, repo: &RepoLocator,
-                      bucket: &Bucket, ui: &mut Summary, context: &str, script: &str) -> Result<(), Error> {
-    let maybe_commit = github.get_last_commit(&repo)?;
-    if let Some(commit) = maybe_commit {
-        let statuses = github.get_statuses(&commit)?;
-        let maybe_status = statuses.iter()
-            .filter(|existing_status|
-                existing_status.context.as_ref().map_or(false, |c| c == context))
-            .next();
-        if let Some(status) = maybe_status {
-            let ui_status = match status.state {
-                State::Pending => ui::Status::Pending,
-                State::Error | State::Failure => ui::Status::Failed,
-                State::Success => ui::Status::Succeeded,
-            };
-            ui.record_build(&commit.sha, ui_status)
+/// Keeps hold of a commit that has been built, so it can be built again on request.
+fn remember_commit(recent_commits: &mut VecDeque<(usize, CommitLocator)>, index: usize, commit: CommitLocator) {
+    if recent_commits.iter().any(|(recent_index, recent)| *recent_index == index && recent.sha == commit.sha) {
+        return;
+    }
+    if recent_commits.len() >= MAX_RECENT_COMMITS {
+        recent_commits.pop_front();
+    }
+    recent_commits.push_back((index, commit));
+}
+
+/// The target index and SHA of the build selected in the terminal.
+fn selected_job(ui: &Summary, targets: &[Arc<Target>]) -> Option<(usize, String)> {
+    let (label, sha) = ui.selected_build()?;
+    let index = targets.iter().position(|target| target.label == label)?;
+    Some((index, sha.to_string()))
+}
+
+fn queue_commits(github: &GitHubClient, target: &Target, queue: &mut VecDeque<CommitLocator>,
+                 all_commits: bool, pull_requests: bool, checks: bool) -> Result<(), Error> {
+    let mut commits = if all_commits {
+        find_unbuilt_commits(github, &target.repo, &target.branch, &target.context, checks)?
+    } else {
+        github.get_last_commit(&target.repo, &target.branch)?.into_iter().collect()
+    };
+    if pull_requests {
+        commits.extend(github.get_pull_request_heads(&target.repo)?);
+    }
+    for commit in commits {
+        enqueue(queue, commit);
+    }
+    Ok(())
+}
+
+/// Queues the recent commits of a target that are still pending but not being built here. The
+/// workers then tell which of them have been orphaned, and rebuild or give up on those.
+fn queue_orphans<F>(github: &GitHubClient, target: &Target, queue: &mut VecDeque<CommitLocator>,
+                    pull_requests: bool, checks: bool, is_building: F) -> Result<(), Error>
+    where F: Fn(&str) -> bool {
+    let mut commits = github.get_commits(&target.repo, &target.branch, 1)?;
+    if pull_requests {
+        commits.extend(github.get_pull_request_heads(&target.repo)?);
+    }
+    for commit in commits {
+        if is_building(&commit.sha) {
+            continue;
+        }
+        let is_pending = if checks {
+            github.get_check_runs(&commit, &target.context)?.iter()
+                .any(|check_run| check_run.status != "completed")
         } else {
-            ui.record_build(&commit.sha, ui::Status::Pending);
-            ui.render()?;
-            github.set_status(&commit, SetStatusRequest {
-                state: State::Pending,
-                target_url: None,
-                description: None, // TODO incorporate machine label
-                context: Some(context),
-            })?;
-            local.reset_to(&commit)?;
-            let path_to_script = format!("{}/{}", &local.path(), &script);
-            let process_output = Command::new("bash")
-                .arg(path_to_script)
-                .output()?;
-            let new_state =
-                if process_output.status.success() {
-                    ui.record_build(&commit.sha, ui::Status::Succeeded);
-                    State::Success
-                } else {
-                    ui.record_build(&commit.sha, ui::Status::Failed);
-                    State::Failure
-                };
-            bucket.put(&format!("{}/stdout.txt", commit.sha), process_output.stdout)?;
-            bucket.put(&format!("{}/stderr.txt", commit.sha), process_output.stderr)?;
-            let build_url = bucket.get_url(&commit.sha);
-            github.set_status(&commit, SetStatusRequest {
-                state: new_state,
-                target_url: Some(&build_url),
-                description: None,
-                context: Some(context),
-            })?;
+            github.get_status(&commit, &target.context)?
+                .map_or(false, |status| match status.state {
+                    State::Pending => true,
+                    _ => false,
+                })
+        };
+        if is_pending {
+            enqueue(queue, commit);
         }
     }
     Ok(())
 }
 
+/// Queues a commit to be rebuilt, whatever its result, for each target whose context a comment
+/// asks to retry. Only comments from people who can push to the repository are obeyed, and they
+/// are given a thumbs up to show they were.
+fn retry_from_comment(github: &GitHubClient, targets: &[Arc<Target>], queues: &mut [VecDeque<CommitLocator>],
+                      forced: &mut HashSet<(usize, String)>, repo: &RepoLocator, comment: &Comment,
+                      command: &str, pull_requests: bool) -> Result<(), Error> {
+    let subject = match comments::subject(comment) {
+        Some(Subject::PullRequest(_)) if !pull_requests => return Ok(()),
+        Some(subject) => subject,
+        None => return Ok(()),
+    };
+    let indices: Vec<usize> = targets.iter()
+        .enumerate()
+        .filter(|(_, target)| target.repo == *repo && comments::requests_retry(&comment.body, command, &target.context))
+        .map(|(index, _)| index)
+        .collect();
+    if indices.is_empty() || !github.can_write(repo, &comment.user.login)? {
+        return Ok(());
+    }
+    let commit = match subject {
+        Subject::PullRequest(number) => github.get_pull_request(repo, number)?,
+        Subject::Commit(sha) => CommitLocator::new(repo, &sha, None),
+    };
+    // Acknowledged first, as the commit would be built twice if this failed and the comment were
+    // dealt with again.
+    github.react_to_comment(repo, comment, "+1")?;
+    for index in indices {
+        enqueue(&mut queues[index], commit.clone());
+        forced.insert((index, commit.sha.to_string()));
+    }
+    Ok(())
+}
+
+/// Whether GitHub refused a request, such as a reaction the App isn't allowed to add. Trying again
+/// wouldn't help, so the comment that led to it is given up on.
+fn is_client_error(error: &Error) -> bool {
+    match error.downcast_ref::<GitHubError>() {
+        Some(GitHubError::ApiError { status, .. }) => *status >= 400 && *status < 500,
+        _ => false,
+    }
+}
+
+fn queue_webhook_event(event: WebhookEvent, targets: &[Arc<Target>], queues: &mut [VecDeque<CommitLocator>],
+                       all_commits: bool, pull_requests: bool) {
+    for (target, queue) in targets.iter().zip(queues.iter_mut()) {
+        match &event {
+            WebhookEvent::Push { owner, repo, branch, shas } => {
+                if *owner != target.repo.owner || *repo != target.repo.repo || *branch != target.branch {
+                    continue;
+                }
+                let skipped = if all_commits { 0 } else { shas.len().saturating_sub(1) };
+                for sha in shas.iter().skip(skipped) {
+                    enqueue(queue, CommitLocator::new(&target.repo, sha, None));
+                }
+            }
+            WebhookEvent::PullRequest { owner, repo, number, base, sha } => {
+                if *owner != target.repo.owner || *repo != target.repo.repo || !pull_requests {
+                    continue;
+                }
+                let pull_request = PullRequestLocator { number: *number, base: base.to_string() };
+                enqueue(queue, CommitLocator::new(&target.repo, sha, Some(pull_request)));
+            }
+            // Comments only matter when retrying on command, and are handled before this.
+            WebhookEvent::Comment { .. } => {}
+        }
+    }
+}
+
+fn enqueue(queue: &mut VecDeque<CommitLocator>, commit: CommitLocator) {
+    if !queue.iter().any(|queued| queued.sha == commit.sha) {
+        queue.push_back(commit);
+    }
+}
+
+/// Walks back from the head of `branch` to the last commit that already has a status, or a check
+/// run in checks mode, for `context`, returning every commit in between, oldest first.
+fn find_unbuilt_commits(github: &GitHubClient, repo: &RepoLocator, branch: &str, context: &str,
+                        checks: bool) -> Result<Vec<CommitLocator>, Error> {
+    let mut unbuilt = vec![];
+    let mut page = 1;
+    'pages: loop {
+        let commits = github.get_commits(repo, branch, page)?;
+        if commits.is_empty() {
+            break;
+        }
+        for commit in commits {
+            let is_built = if checks {
+                github.get_check_run(&commit, context)?.is_some()
+            } else {
+                github.get_status(&commit, context)?.is_some()
+            };
+            if is_built || unbuilt.len() >= MAX_UNBUILT_COMMITS {
+                break 'pages;
+            }
+            unbuilt.push(commit);
+        }
+        page += 1;
+    }
+    unbuilt.reverse();
+    Ok(unbuilt)
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return "localhost".to_string();
+    }
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..length]).to_string()
+}
+
 fn set_up_panic_handler() {
     panic::set_hook(Box::new(|info: &PanicInfo| {
         File::create("./panic.log").ok().into_iter().for_each(|mut log_file| {
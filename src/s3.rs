@@ -1,25 +1,67 @@
 use std::str::FromStr;
 use failure::Error;
-use rusoto_s3::{S3, S3Client, PutObjectRequest};
+use futures::Future;
+use rusoto_s3::{S3, S3Client, GetObjectError, GetObjectRequest, ListObjectsV2Request, PutObjectRequest};
+use rusoto_s3::util::{PreSignedRequest, PreSignedRequestOption};
+use std::io::Read;
+use std::time::Duration;
 use rusoto_core::Region;
 use rusoto_core::ByteStream;
+use rusoto_core::DefaultCredentialsProvider;
+use rusoto_core::ProvideAwsCredentials;
+use rusoto_core::credential::AwsCredentials;
+use crate::store::LogStore;
+
+const INDEX_NAME: &str = "index.html";
 
 pub struct Bucket {
     region: String,
     bucket: String,
     key_prefix: String,
+    presign_expiry: Option<Duration>,
 }
 
 impl Bucket {
-    pub fn new(region: String, bucket: String, key_prefix: String) -> Self {
+    /// With a `presign_expiry`, links to logs are presigned URLs that anyone can open until they
+    /// expire. Otherwise they point at the S3 console, which needs access to the AWS account.
+    pub fn new(region: String, bucket: String, key_prefix: String, presign_expiry: Option<Duration>) -> Self {
         Bucket {
             region,
             bucket,
             key_prefix,
+            presign_expiry,
         }
     }
 
-    pub fn put(&self, key: &str, content: Vec<u8>) -> Result<(), Error> {
+    fn presign(&self, region: &Region, credentials: &AwsCredentials, key: &str, expires_in: Duration) -> String {
+        let request = GetObjectRequest {
+            bucket: self.bucket.to_string(),
+            key: format!("{}/{}", &self.key_prefix, &key),
+            ..Default::default()
+        };
+        request.get_presigned_url(region, credentials, &PreSignedRequestOption { expires_in })
+    }
+
+    /// Saves an index page linking to each log beneath `key` and returns a presigned URL for it.
+    fn presign_index(&self, key: &str, expires_in: Duration) -> Result<String, Error> {
+        let region = Region::from_str(&self.region)?;
+        let credentials = DefaultCredentialsProvider::new()?.credentials().wait()?;
+        let mut links = String::new();
+        for entry in self.list(key)? {
+            if entry.ends_with('/') || entry == INDEX_NAME {
+                continue;
+            }
+            let url = self.presign(&region, &credentials, &format!("{}/{}", key, &entry), expires_in);
+            links.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n", url.replace('&', "&amp;"), &entry));
+        }
+        let index = format!("<!DOCTYPE html>\n<html><head><title>{}</title></head>\n<body><h1>{}</h1>\n<ul>\n{}</ul></body></html>\n",
+                            key, key, links);
+        let index_key = format!("{}/{}", key, INDEX_NAME);
+        self.put_with_type(&index_key, index.into_bytes(), Some("text/html; charset=utf-8"))?;
+        Ok(self.presign(&region, &credentials, &index_key, expires_in))
+    }
+
+    fn put_with_type(&self, key: &str, content: Vec<u8>, content_type: Option<&str>) -> Result<(), Error> {
         let region = Region::from_str(&self.region)?;
         let client = S3Client::new(region);
         let body: ByteStream = ByteStream::from(content);
@@ -33,7 +75,7 @@ impl Bucket {
             content_language: None,
             content_length: None,
             content_md5: None,
-            content_type: None,
+            content_type: content_type.map(str::to_string),
             expires: None,
             grant_full_control: None,
             grant_read: None,
@@ -53,9 +95,71 @@ impl Bucket {
         }).sync()?;
         Ok(())
     }
+}
+
+impl LogStore for Bucket {
+    fn put(&self, key: &str, content: Vec<u8>) -> Result<(), Error> {
+        self.put_with_type(key, content, None)
+    }
 
-    pub fn get_url(&self, key: &str) -> String {
-        format!("https://s3.console.aws.amazon.com/s3/buckets/{}/{}/{}/?region={}&tab=overview",
-                &self.bucket, &self.key_prefix, &key, &self.region)
+    fn get_url(&self, key: &str) -> Result<String, Error> {
+        if let Some(expires_in) = self.presign_expiry {
+            return self.presign_index(key, expires_in);
+        }
+        Ok(format!("https://s3.console.aws.amazon.com/s3/buckets/{}/{}/{}/?region={}&tab=overview",
+                &self.bucket, &self.key_prefix, &key, &self.region))
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let region = Region::from_str(&self.region)?;
+        let client = S3Client::new(region);
+        let output = client.get_object(GetObjectRequest {
+            bucket: self.bucket.to_string(),
+            key: format!("{}/{}", &self.key_prefix, &key),
+            ..Default::default()
+        }).sync();
+        let output = match output {
+            Ok(output) => output,
+            Err(GetObjectError::NoSuchKey(_)) => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let mut content = vec![];
+        if let Some(body) = output.body {
+            body.into_blocking_read().read_to_end(&mut content)?;
+        }
+        Ok(Some(content))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let region = Region::from_str(&self.region)?;
+        let client = S3Client::new(region);
+        let full_prefix = if prefix.is_empty() {
+            format!("{}/", &self.key_prefix)
+        } else {
+            format!("{}/{}/", &self.key_prefix, prefix.trim_end_matches('/'))
+        };
+        let mut entries = vec![];
+        let mut continuation_token = None;
+        loop {
+            let output = client.list_objects_v2(ListObjectsV2Request {
+                bucket: self.bucket.to_string(),
+                prefix: Some(full_prefix.to_string()),
+                delimiter: Some("/".to_string()),
+                continuation_token,
+                ..Default::default()
+            }).sync()?;
+            let directories = output.common_prefixes.unwrap_or_default().into_iter()
+                .filter_map(|common_prefix| common_prefix.prefix);
+            let objects = output.contents.unwrap_or_default().into_iter()
+                .filter_map(|object| object.key);
+            entries.extend(directories.chain(objects)
+                .map(|key| key[full_prefix.len()..].to_string()));
+            continuation_token = output.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        entries.sort();
+        Ok(entries)
     }
 }
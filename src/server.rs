@@ -0,0 +1,96 @@
+use crate::target::Target;
+use failure::Error;
+use failure::format_err;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use tiny_http::Header;
+use tiny_http::Method;
+use tiny_http::Request;
+use tiny_http::Response;
+use tiny_http::Server;
+
+/// Serves the saved build logs of every target over HTTP, so anyone who can see a commit status
+/// can follow its link and read the output.
+///
+/// Each target's logs are found under its key prefix: `/<prefix>/` lists the commits that have
+/// been built, `/<prefix>/<sha>/` lists the logs of one build, and anything deeper is a log.
+pub fn serve(port: u16, targets: Vec<Arc<Target>>) -> Result<JoinHandle<()>, Error> {
+    let server = Server::http(("0.0.0.0", port))
+        .map_err(|e| format_err!("Could not listen on port {}: {}", port, e))?;
+    Ok(thread::spawn(move || {
+        for request in server.incoming_requests() {
+            handle(request, &targets);
+        }
+    }))
+}
+
+fn handle(request: Request, targets: &[Arc<Target>]) {
+    let path = request.url().split('?').next().unwrap_or("").to_string();
+    let response = if *request.method() != Method::Get {
+        error(405, "Method not allowed")
+    } else if path.split('/').any(|segment| segment == "..") {
+        error(400, "Bad request")
+    } else if path == "/" {
+        let entries: Vec<String> = targets.iter()
+            .map(|target| format!("{}/", &target.key_prefix))
+            .collect();
+        html_index("Crane", "/", &entries)
+    } else {
+        let path = path.trim_start_matches('/');
+        let target = targets.iter()
+            .find(|target| path.starts_with(&format!("{}/", &target.key_prefix)));
+        match target {
+            Some(target) => respond_for_target(target, &path[target.key_prefix.len() + 1..]),
+            None => error(404, "Not found"),
+        }
+    };
+    request.respond(response).unwrap_or(());
+}
+
+fn respond_for_target(target: &Target, key: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    if key.is_empty() || key.ends_with('/') {
+        match target.store.list(key.trim_end_matches('/')) {
+            Ok(entries) => {
+                let title = format!("{} {}", &target.label, key.trim_end_matches('/'));
+                html_index(&title, &format!("/{}/{}", &target.key_prefix, key), &entries)
+            }
+            Err(e) => error(502, &format!("{}", e)),
+        }
+    } else {
+        match target.store.get(key) {
+            Ok(Some(content)) => Response::from_data(content)
+                .with_header(header("Content-Type", "text/plain; charset=utf-8")),
+            Ok(None) => error(404, "Not found"),
+            Err(e) => error(502, &format!("{}", e)),
+        }
+    }
+}
+
+fn html_index(title: &str, base_path: &str, entries: &[String]) -> Response<std::io::Cursor<Vec<u8>>> {
+    let links: String = entries.iter()
+        .map(|entry| format!("<li><a href=\"{}{}\">{}</a></li>\n",
+                             escape(base_path), escape(entry), escape(entry)))
+        .collect();
+    let body = format!("<!DOCTYPE html>\n<html><head><title>{}</title></head>\n<body><h1>{}</h1>\n<ul>\n{}</ul></body></html>\n",
+                       escape(title), escape(title), links);
+    Response::from_string(body)
+        .with_header(header("Content-Type", "text/html; charset=utf-8"))
+}
+
+fn error(status: u16, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(message)
+        .with_status_code(status)
+        .with_header(header("Content-Type", "text/plain; charset=utf-8"))
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
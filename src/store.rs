@@ -0,0 +1,83 @@
+use failure::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// Somewhere build logs can be saved and later linked to from a commit status.
+pub trait LogStore: Send + Sync {
+    fn put(&self, key: &str, content: Vec<u8>) -> Result<(), Error>;
+
+    /// The URL at which the logs saved beneath `key` can be read. Stores may need to save
+    /// something themselves to produce it, such as an index page.
+    fn get_url(&self, key: &str) -> Result<String, Error>;
+
+    /// Reads a saved log back, or `None` if nothing was saved under `key`.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Lists the entries directly beneath `prefix`, which may be empty to list the top level.
+    /// Entries that contain further entries end with a `/`.
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error>;
+}
+
+/// Saves logs beneath a directory on the local filesystem, for running without any cloud storage.
+pub struct LocalDirectory {
+    root: PathBuf,
+    key_prefix: String,
+    base_url: Option<String>,
+}
+
+impl LocalDirectory {
+    /// Logs are linked to with `file://` URLs unless a `base_url` that serves `root` is given.
+    pub fn new(root: PathBuf, key_prefix: String, base_url: Option<String>) -> Self {
+        LocalDirectory {
+            root,
+            key_prefix,
+            base_url,
+        }
+    }
+}
+
+impl LogStore for LocalDirectory {
+    fn put(&self, key: &str, content: Vec<u8>) -> Result<(), Error> {
+        let path = self.root.join(&self.key_prefix).join(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    fn get_url(&self, key: &str) -> Result<String, Error> {
+        let url = match &self.base_url {
+            Some(base_url) => format!("{}/{}/{}/", base_url.trim_end_matches('/'), &self.key_prefix, &key),
+            None => format!("file://{}/", self.root.join(&self.key_prefix).join(key).display()),
+        };
+        Ok(url)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let path = self.root.join(&self.key_prefix).join(key);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read(path)?))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let path = self.root.join(&self.key_prefix).join(prefix);
+        if !path.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut entries = vec![];
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_dir() {
+                entries.push(format!("{}/", name));
+            } else {
+                entries.push(name);
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+}
@@ -0,0 +1,63 @@
+use crate::args::StoreArgs;
+use crate::args::TargetArgs;
+use crate::hub::RepoLocator;
+use crate::s3::Bucket;
+use crate::store::LocalDirectory;
+use crate::store::LogStore;
+use failure::Error;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A branch being watched by the agent, and where the logs of its builds are kept.
+pub struct Target {
+    pub label: String,
+    pub repo: RepoLocator,
+    pub branch: String,
+    pub context: String,
+    pub script: String,
+    pub key_prefix: String,
+    pub store: Box<dyn LogStore>,
+    public_url: Option<String>,
+    pub log_root: PathBuf,
+}
+
+impl Target {
+    /// When `public_url` is given, statuses link to the crane log server there rather than to the store.
+    pub fn new(args: TargetArgs, store: &StoreArgs, public_url: Option<&str>, workspace: &Path) -> Self {
+        let repo = RepoLocator {
+            owner: args.owner,
+            repo: args.repository,
+        };
+        let label = format!("{}/{}@{} {}", &repo.owner, &repo.repo, &args.branch, &args.context);
+        let key_prefix = format!("build/logs/{}/{}/{}/{}",
+                                 &repo.owner, &repo.repo, &args.branch, &args.context);
+        let store: Box<dyn LogStore> = match store {
+            StoreArgs::S3 { region, bucket, presign_expiry } =>
+                Box::new(Bucket::new(region.to_string(), bucket.to_string(), key_prefix.to_string(),
+                                     *presign_expiry)),
+            StoreArgs::LocalDirectory { path, base_url } =>
+                Box::new(LocalDirectory::new(PathBuf::from(path), key_prefix.to_string(), base_url.clone())),
+        };
+        let log_root = workspace.join("logs").join(&repo.owner).join(&repo.repo).join(&args.branch)
+            .join(&args.context);
+        Target {
+            label,
+            repo,
+            branch: args.branch,
+            context: args.context,
+            script: args.script,
+            key_prefix,
+            store,
+            public_url: public_url.map(|url| url.trim_end_matches('/').to_string()),
+            log_root,
+        }
+    }
+
+    /// The URL at which the logs saved under `key` can be read.
+    pub fn get_url(&self, key: &str) -> Result<String, Error> {
+        match &self.public_url {
+            Some(public_url) => Ok(format!("{}/{}/{}/", public_url, &self.key_prefix, key)),
+            None => self.store.get_url(key),
+        }
+    }
+}
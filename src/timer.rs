@@ -2,20 +2,33 @@ use std::time::Duration;
 use std::time::Instant;
 use rand::prelude::*;
 use rand::distributions::Exp;
+use std::cmp::max;
 use std::cmp::min;
 
 pub struct RandomExpBackoffTimer {
     rng: ThreadRng,
     exp: Exp,
     due_time: Instant,
+    min_millis: u64,
+    scale_millis: f64,
+    max_millis: u64,
 }
 
 impl RandomExpBackoffTimer {
     pub fn new() -> Self {
+        RandomExpBackoffTimer::with_range(2000, 3000, 20000)
+    }
+
+    /// Waits at least `min_millis` and at most `max_millis`, plus a random, exponentially
+    /// distributed amount with a mean of `scale_millis` in between.
+    pub fn with_range(min_millis: u64, scale_millis: u64, max_millis: u64) -> Self {
         RandomExpBackoffTimer {
             rng: rand::thread_rng(),
             exp: Exp::new(1.0),
             due_time: Instant::now(),
+            min_millis,
+            scale_millis: scale_millis as f64,
+            max_millis,
         }
     }
 
@@ -25,9 +38,15 @@ impl RandomExpBackoffTimer {
 
     pub fn reset(&mut self) -> Instant {
         let x = self.exp.sample(&mut self.rng);
-        let millis_to_wait = min(2000 + ((x * 3000.0).floor() as u64), 20000);
+        let millis_to_wait = min(self.min_millis + ((x * self.scale_millis).floor() as u64), self.max_millis);
         let duration = Duration::from_millis(millis_to_wait);
         self.due_time = Instant::now() + duration;
         self.due_time
     }
+
+    /// Pushes the due time back to at least `time`, returning the new due time.
+    pub fn delay_until(&mut self, time: Instant) -> Instant {
+        self.due_time = max(self.due_time, time);
+        self.due_time
+    }
 }
@@ -18,6 +18,22 @@ use std::time::Duration;
 use std::cmp::max;
 use std::cmp::min;
 
+/// Something asked for at the keyboard.
+pub enum Command {
+    /// Move the selection in the builds table up, to a newer build.
+    SelectNewer,
+    /// Move the selection in the builds table down, to an older build.
+    SelectOlder,
+    /// Build the selected commit again, replacing its result.
+    Rebuild,
+    /// Stop the selected build if it is running.
+    Cancel,
+    /// Stop or restart polling GitHub for new commits.
+    TogglePolling,
+    /// Poll GitHub for new commits straight away.
+    PollNow,
+}
+
 #[derive(Clone, Copy)]
 pub enum Status {
     Succeeded,
@@ -102,34 +118,78 @@ impl PropertyTable {
     }
 }
 
+struct TargetState {
+    label: String,
+    status: Option<Status>,
+}
+
+struct TargetTable {
+    targets: Vec<TargetState>,
+}
+
+impl TargetTable {
+    fn render<B>(&self, frame: &mut Frame<B>, area: Rect) where B: Backend {
+        let rows = self.targets.iter()
+            .map(|target| match target.status {
+                Some(status) => Row::StyledData(
+                    vec![target.label.to_string(), status.text().to_string()].into_iter(), status.secondary_style()),
+                None => Row::Data(
+                    vec![target.label.to_string(), "Waiting".to_string()].into_iter()),
+            });
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title("Targets");
+
+        Table::new(["Target", "Status"].iter(), rows)
+            .widths(&[40, 12])
+            .header_style(Style::default().fg(Color::DarkGray))
+            .block(block)
+            .render(frame, area)
+    }
+}
+
 struct BuildResult {
+    target: String,
     sha: String,
     status: Status,
 }
 
 struct BuildTable {
     builds: Vec<BuildResult>,
+    /// The index in `builds` of the selected build, if any.
+    selected: Option<usize>,
 }
 
 impl BuildTable {
     fn new() -> Self {
         BuildTable {
-            builds: vec![]
+            builds: vec![],
+            selected: None,
         }
     }
 
     fn render<B>(&self, frame: &mut Frame<B>, area: Rect) where B: Backend {
         let rows = self.builds.iter()
+            .enumerate()
             .rev()
-            .map(|result| Row::StyledData(
-                vec![result.sha.to_string(), result.status.text().to_string()].into_iter(), result.status.secondary_style()));
+            .map(|(index, result)| {
+                let style = if self.selected == Some(index) {
+                    result.status.secondary_style().modifier(Modifier::Invert)
+                } else {
+                    result.status.secondary_style()
+                };
+                Row::StyledData(
+                    vec![result.target.to_string(), result.sha.to_string(), result.status.text().to_string()].into_iter(),
+                    style)
+            });
 
         let block = Block::default()
             .borders(Borders::ALL)
-            .title("Builds");
+            .title("Builds (\u{2191}\u{2193} select, r rebuild, c cancel, p pause, n poll now)");
 
-        Table::new(["Commit", "Status"].iter(), rows)
-            .widths(&[12, 18])
+        Table::new(["Target", "Commit", "Status"].iter(), rows)
+            .widths(&[40, 12, 12])
             .header_style(Style::default().fg(Color::DarkGray))
             .block(block)
             .render(frame, area)
@@ -174,6 +234,35 @@ impl RetryWindow {
     }
 }
 
+struct BuildOutput {
+    lines: Vec<String>,
+}
+
+impl BuildOutput {
+    fn new() -> Self {
+        BuildOutput {
+            lines: vec![]
+        }
+    }
+
+    fn render<B>(&self, frame: &mut Frame<B>, area: Rect) where B: Backend {
+        let visible_lines = area.height.saturating_sub(2) as usize;
+        let skipped_lines = self.lines.len().saturating_sub(visible_lines);
+        let lines: Vec<Text> = self.lines.iter()
+            .skip(skipped_lines)
+            .map(|line| Text::raw(format!("{}\n", line)))
+            .collect();
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title("Output");
+
+        Paragraph::new(lines.iter())
+            .block(block)
+            .render(frame, area)
+    }
+}
+
 struct LastError {
     error: Option<Error>,
 }
@@ -209,13 +298,15 @@ pub struct Summary {
     terminal: Terminal<TermionBackend<AlternateScreen<MouseTerminal<RawTerminal<Stdout>>>>>,
     status: Status,
     property_table: PropertyTable,
+    target_table: TargetTable,
     retry_window: RetryWindow,
     build_table: BuildTable,
+    build_output: BuildOutput,
     last_error: LastError,
 }
 
 impl Summary {
-    pub fn new(properties: Vec<Property>) -> Result<Self, Error> {
+    pub fn new(properties: Vec<Property>, targets: Vec<String>) -> Result<Self, Error> {
         let stdout = io::stdout().into_raw_mode()?;
         let stdout = MouseTerminal::from(stdout);
         let stdout = AlternateScreen::from(stdout);
@@ -226,8 +317,14 @@ impl Summary {
             status: Status::Pending,
             terminal,
             property_table: PropertyTable { properties },
+            target_table: TargetTable {
+                targets: targets.into_iter()
+                    .map(|label| TargetState { label, status: None })
+                    .collect()
+            },
             retry_window: RetryWindow::new(),
             build_table: BuildTable::new(),
+            build_output: BuildOutput::new(),
             last_error: LastError::new()
         };
         Ok(summary)
@@ -236,8 +333,10 @@ impl Summary {
     pub fn render(&mut self) -> Result<(), Error> {
         let status = &self.status;
         let property_table = &self.property_table;
+        let target_table = &self.target_table;
         let retry_window = &self.retry_window;
         let build_table = &self.build_table;
+        let build_output = &self.build_output;
         let last_error = &self.last_error;
 
         self.terminal.draw(|mut frame| {
@@ -248,19 +347,21 @@ impl Summary {
 
             let left_vertical_pane = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints(vec![Constraint::Length(5), Constraint::Min(10), Constraint::Length(5)])
+                .constraints(vec![Constraint::Length(5), Constraint::Length(10), Constraint::Min(5), Constraint::Length(5)])
                 .split(outer_horizontal_pane[0]);
 
             let right_vertical_pane = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints(vec![Constraint::Length(5), Constraint::Min(5)])
+                .constraints(vec![Constraint::Length(5), Constraint::Length(14), Constraint::Min(5)])
                 .split(outer_horizontal_pane[1]);
 
             status.render(&mut frame, left_vertical_pane[0]);
             property_table.render(&mut frame, left_vertical_pane[1]);
+            target_table.render(&mut frame, left_vertical_pane[2]);
             retry_window.render(&mut frame, right_vertical_pane[0]);
             build_table.render(&mut frame, right_vertical_pane[1]);
-            last_error.render(&mut frame, left_vertical_pane[2]);
+            build_output.render(&mut frame, right_vertical_pane[2]);
+            last_error.render(&mut frame, left_vertical_pane[3]);
         })?;
         Ok(())
     }
@@ -270,12 +371,18 @@ impl Summary {
         self.retry_window.due_time = due_time;
     }
 
-    pub fn record_build(&mut self, sha: &str, status: Status) {
+    pub fn record_build(&mut self, target: &str, sha: &str, status: Status) {
         self.status = status;
 
+        for target_state in &mut self.target_table.targets {
+            if target_state.label == target {
+                target_state.status = Some(status);
+            }
+        }
+
         let mut has_seen_build = false;
         for build in &mut self.build_table.builds {
-            if build.sha == sha {
+            if build.target == target && build.sha == sha {
                 build.status = status;
                 has_seen_build = true;
             }
@@ -285,18 +392,57 @@ impl Summary {
             return;
         }
 
-        let builds = &mut self.build_table.builds;
+        let build_table = &mut self.build_table;
 
-        if builds.len() >= 10 {
-            builds.remove(0);
+        if build_table.builds.len() >= 10 {
+            build_table.builds.remove(0);
+            build_table.selected = match build_table.selected {
+                Some(0) | None => None,
+                Some(index) => Some(index - 1),
+            };
         }
 
+        let builds = &mut build_table.builds;
+
         builds.push(BuildResult {
+            target: target.to_string(),
             sha: sha.to_string(),
             status,
         });
     }
 
+    /// Moves the selection in the builds table, starting from the newest build.
+    pub fn select_build(&mut self, command: &Command) {
+        let count = self.build_table.builds.len();
+        if count == 0 {
+            return;
+        }
+        self.build_table.selected = Some(match (command, self.build_table.selected) {
+            (Command::SelectNewer, Some(index)) => min(index + 1, count - 1),
+            (Command::SelectOlder, Some(index)) => index.saturating_sub(1),
+            _ => count - 1,
+        });
+    }
+
+    /// The target label and SHA of the selected build.
+    pub fn selected_build(&self) -> Option<(&str, &str)> {
+        let build = &self.build_table.builds[self.build_table.selected?];
+        Some((&build.target, &build.sha))
+    }
+
+    pub fn record_output(&mut self, lines: Vec<String>) {
+        self.build_output.lines = lines;
+    }
+
+    /// Shows `value` against the property called `name`, adding it if it isn't shown yet.
+    pub fn set_property(&mut self, name: &str, value: &str) {
+        let properties = &mut self.property_table.properties;
+        match properties.iter_mut().find(|p| p.name == name) {
+            Some(property) => property.value = value.to_string(),
+            None => properties.push(Property::new(name, value)),
+        }
+    }
+
     pub fn record_error(&mut self, error: Error) {
         self.last_error.error = Some(error);
     }
@@ -0,0 +1,246 @@
+use crate::hub::responses::Comment;
+use failure::Error;
+use failure::format_err;
+use hmac::Hmac;
+use hmac::Mac;
+use sha1::Sha1;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::thread::JoinHandle;
+use tiny_http::Method;
+use tiny_http::Request;
+use tiny_http::Response;
+use tiny_http::Server;
+
+const SIGNATURE_HEADER: &str = "X-Hub-Signature";
+const SIGNATURE_PREFIX: &str = "sha1=";
+const EVENT_HEADER: &str = "X-GitHub-Event";
+const BRANCH_REF_PREFIX: &str = "refs/heads/";
+
+/// Commits and comments announced by a GitHub webhook.
+#[derive(Debug)]
+pub enum WebhookEvent {
+    /// Commits pushed to a branch, oldest first. The last one is the new head of the branch.
+    Push { owner: String, repo: String, branch: String, shas: Vec<String> },
+    /// A pull request's head commit, which is built as if merged into its `base` branch.
+    PullRequest { owner: String, repo: String, number: u32, base: String, sha: String },
+    /// A new comment on an issue, pull request or commit.
+    Comment { owner: String, repo: String, comment: Comment },
+}
+
+/// Listens for GitHub webhooks, forwarding the commits of any correctly signed `push` and
+/// `pull_request` events, and the comments of `issue_comment` and `commit_comment` events, to
+/// `events`.
+pub fn listen(port: u16, secret: String, events: Sender<WebhookEvent>) -> Result<JoinHandle<()>, Error> {
+    let server = Server::http(("0.0.0.0", port))
+        .map_err(|e| format_err!("Could not listen for webhooks on port {}: {}", port, e))?;
+    Ok(thread::spawn(move || {
+        for request in server.incoming_requests() {
+            if let Some(event) = handle(request, &secret) {
+                if events.send(event).is_err() {
+                    return;
+                }
+            }
+        }
+    }))
+}
+
+fn handle(mut request: Request, secret: &str) -> Option<WebhookEvent> {
+    let (status, event) = if *request.method() != Method::Post {
+        (405, None)
+    } else {
+        let signature = header_value(&request, SIGNATURE_HEADER);
+        let event_type = header_value(&request, EVENT_HEADER);
+        let mut body = vec![];
+        match request.as_reader().read_to_end(&mut body) {
+            Err(_) => (400, None),
+            Ok(_) if !is_signed(secret, &body, signature.as_ref().map(String::as_str)) => (401, None),
+            Ok(_) => match parse_event(event_type.as_ref().map(String::as_str), &body) {
+                Ok(Some(event)) => (202, Some(event)),
+                Ok(None) => (204, None),
+                Err(_) => (400, None),
+            },
+        }
+    };
+    request.respond(Response::empty(status)).unwrap_or(());
+    event
+}
+
+fn header_value(request: &Request, name: &'static str) -> Option<String> {
+    request.headers().iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str().to_string())
+}
+
+/// Checks the `sha1=<hex>` HMAC that GitHub signs each delivery with.
+fn is_signed(secret: &str, body: &[u8], signature: Option<&str>) -> bool {
+    let code = signature
+        .filter(|signature| signature.starts_with(SIGNATURE_PREFIX))
+        .and_then(|signature| hex::decode(&signature[SIGNATURE_PREFIX.len()..]).ok());
+    let code = match code {
+        Some(code) => code,
+        None => return false,
+    };
+    let mut mac = match Hmac::<Sha1>::new(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.input(body);
+    mac.verify(&code).is_ok()
+}
+
+fn parse_event(event_type: Option<&str>, body: &[u8]) -> Result<Option<WebhookEvent>, Error> {
+    match event_type {
+        Some("push") => {
+            let push: payloads::Push = serde_json::from_slice(body)?;
+            if push.deleted || !push.git_ref.starts_with(BRANCH_REF_PREFIX) {
+                return Ok(None);
+            }
+            let branch = push.git_ref[BRANCH_REF_PREFIX.len()..].to_string();
+            let (owner, repo) = split_full_name(&push.repository.full_name)?;
+            let mut shas: Vec<String> = push.commits.into_iter().map(|commit| commit.id).collect();
+            if shas.last() != Some(&push.after) {
+                shas.push(push.after);
+            }
+            Ok(Some(WebhookEvent::Push { owner, repo, branch, shas }))
+        }
+        Some("pull_request") => {
+            let event: payloads::PullRequestEvent = serde_json::from_slice(body)?;
+            match event.action.as_str() {
+                "opened" | "reopened" | "synchronize" => {
+                    let (owner, repo) = split_full_name(&event.repository.full_name)?;
+                    Ok(Some(WebhookEvent::PullRequest {
+                        owner,
+                        repo,
+                        number: event.number,
+                        base: event.pull_request.base.git_ref,
+                        sha: event.pull_request.head.sha,
+                    }))
+                }
+                _ => Ok(None),
+            }
+        }
+        Some("issue_comment") | Some("commit_comment") => {
+            let event: payloads::CommentEvent = serde_json::from_slice(body)?;
+            if event.action != "created" {
+                return Ok(None);
+            }
+            let (owner, repo) = split_full_name(&event.repository.full_name)?;
+            Ok(Some(WebhookEvent::Comment { owner, repo, comment: event.comment }))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn split_full_name(full_name: &str) -> Result<(String, String), Error> {
+    let mut parts = full_name.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(owner), Some(repo)) => Ok((owner.to_string(), repo.to_string())),
+        _ => Err(format_err!("{} is not a repository name", full_name)),
+    }
+}
+
+mod payloads {
+    use crate::hub::responses::Comment;
+    use crate::hub::responses::PullRequest;
+
+    #[derive(Deserialize, Debug)]
+    pub struct Repository {
+        pub full_name: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct PushedCommit {
+        pub id: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Push {
+        #[serde(rename = "ref")]
+        pub git_ref: String,
+        pub after: String,
+        #[serde(default)]
+        pub deleted: bool,
+        #[serde(default)]
+        pub commits: Vec<PushedCommit>,
+        pub repository: Repository,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct PullRequestEvent {
+        pub action: String,
+        pub number: u32,
+        pub pull_request: PullRequest,
+        pub repository: Repository,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct CommentEvent {
+        pub action: String,
+        pub comment: Comment,
+        pub repository: Repository,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example from GitHub's documentation on validating webhook deliveries.
+    const SECRET: &str = "It's a Secret to Everybody";
+    const PAYLOAD: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str = "sha1=01dc10d0c83e72ed246219cdd91669667fe2ca59";
+
+    #[test]
+    fn accepts_good_signature() {
+        assert!(is_signed(SECRET, PAYLOAD, Some(SIGNATURE)));
+    }
+
+    #[test]
+    fn rejects_bad_signature() {
+        assert!(!is_signed(SECRET, PAYLOAD, Some("sha1=01dc10d0c83e72ed246219cdd91669667fe2ca58")));
+        assert!(!is_signed("Not the secret", PAYLOAD, Some(SIGNATURE)));
+        assert!(!is_signed(SECRET, b"Hello, World?", Some(SIGNATURE)));
+        assert!(!is_signed(SECRET, PAYLOAD, Some("sha1=not hex")));
+    }
+
+    #[test]
+    fn rejects_missing_signature() {
+        assert!(!is_signed(SECRET, PAYLOAD, None));
+        assert!(!is_signed(SECRET, PAYLOAD, Some("")));
+    }
+
+    #[test]
+    fn rejects_signature_without_prefix() {
+        assert!(!is_signed(SECRET, PAYLOAD, Some(&SIGNATURE["sha1=".len()..])));
+        assert!(!is_signed(SECRET, PAYLOAD, Some(&SIGNATURE.replace("sha1=", "sha256="))));
+    }
+
+    fn push(git_ref: &str, deleted: bool) -> Vec<u8> {
+        format!(r#"{{"ref": "{}", "after": "{}", "deleted": {}, "commits": [],
+                    "repository": {{"full_name": "owner/repo"}}}}"#,
+                git_ref, if deleted { "0000000000000000000000000000000000000000" } else { "abc123" }, deleted)
+            .into_bytes()
+    }
+
+    #[test]
+    fn parses_push_to_branch() {
+        match parse_event(Some("push"), &push("refs/heads/master", false)).unwrap() {
+            Some(WebhookEvent::Push { owner, repo, branch, shas }) => {
+                assert_eq!((owner.as_str(), repo.as_str(), branch.as_str()), ("owner", "repo", "master"));
+                assert_eq!(shas, vec!["abc123".to_string()]);
+            }
+            other => panic!("Expected a push, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ignores_deleted_branch() {
+        assert!(parse_event(Some("push"), &push("refs/heads/master", true)).unwrap().is_none());
+    }
+
+    #[test]
+    fn ignores_tags() {
+        assert!(parse_event(Some("push"), &push("refs/tags/v1.0", false)).unwrap().is_none());
+    }
+}
@@ -0,0 +1,681 @@
+use crate::build;
+use crate::build::Build;
+use crate::checks;
+use crate::history;
+use crate::history::BuildRecord;
+use crate::claim;
+use crate::claim::Claim;
+use crate::claim::OrphanPolicy;
+use crate::hub::CommitLocator;
+use crate::hub::GitHubClient;
+use crate::hub::GitHubError;
+use crate::hub::common::State;
+use crate::hub::requests::CreateCheckRunRequest;
+use crate::hub::requests::SetStatusRequest;
+use crate::hub::requests::UpdateCheckRunRequest;
+use crate::hub::responses::CheckRun;
+use crate::local::CleanPolicy;
+use crate::local::GitCredentials;
+use crate::local::LocalRepo;
+use crate::local::LocalRepoError;
+use crate::store::LogStore;
+use crate::target::Target;
+use crate::ui;
+use failure::Error;
+use failure::format_err;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const POLL_PERIOD: Duration = Duration::from_millis(64);
+const UPLOAD_PERIOD: Duration = Duration::from_secs(10);
+const RATE_LIMIT_POLL_PERIOD: Duration = Duration::from_secs(1);
+/// GitHub rejects status descriptions longer than this.
+const MAX_DESCRIPTION_LENGTH: usize = 140;
+/// A build script can print a line starting with this to choose its own status description.
+const DESCRIPTION_MARKER: &str = "::crane-description::";
+
+/// A request to build one commit of one target.
+pub struct Job {
+    pub target_index: usize,
+    pub target: Arc<Target>,
+    pub commit: CommitLocator,
+    /// Build the commit even if it already has a result.
+    pub force: bool,
+    /// Set to stop the build.
+    pub cancelled: Arc<AtomicBool>,
+}
+
+/// Progress reported by workers back to the UI thread.
+pub enum Event {
+    Started { target_index: usize, sha: String },
+    Status { target_index: usize, sha: String, status: ui::Status },
+    Output { target_index: usize, sha: String, lines: Vec<String> },
+    /// Something went wrong that didn't stop the build.
+    Error { error: Error },
+    /// A build has finished, and should be added to the history.
+    Finished { record: BuildRecord },
+    Done { target_index: usize, sha: String, result: Result<(), Error> },
+}
+
+pub struct WorkerSettings {
+    /// Identifies this agent's claims on builds to other agents watching the same contexts.
+    pub agent_id: String,
+    /// How long another agent's claim lasts without a heartbeat.
+    pub orphan_age: Duration,
+    /// What to do with builds left pending by agents that stopped, this one included.
+    pub orphans: OrphanPolicy,
+    /// Names this agent in the descriptions of the statuses it posts.
+    pub label: String,
+    pub git_url: String,
+    /// Key to clone with when `git_url` is an `ssh://` URL. The ssh-agent is used without one.
+    pub ssh_key_file: Option<PathBuf>,
+    pub workspace: PathBuf,
+    pub clean: CleanPolicy,
+    /// Fetch only this many commits of history.
+    pub fetch_depth: Option<u32>,
+    pub timeout: Option<Duration>,
+    /// Report on builds with check runs rather than commit statuses.
+    pub checks: bool,
+}
+
+/// A fixed number of threads that build jobs in parallel, each in its own working copies.
+pub struct WorkerPool {
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    events: Receiver<Event>,
+}
+
+impl WorkerPool {
+    pub fn new(size: usize, github: Arc<GitHubClient>, settings: WorkerSettings,
+               running: Arc<AtomicBool>) -> Self {
+        let (job_sender, job_receiver) = mpsc::channel();
+        let (event_sender, event_receiver) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let settings = Arc::new(settings);
+        let cache_lock = Arc::new(Mutex::new(()));
+        // Statuses are only timestamped to the second, so this is too.
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let started_at = UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs());
+        let workers = (0..size)
+            .map(|index| {
+                let worker = Worker {
+                    index,
+                    github: github.clone(),
+                    settings: settings.clone(),
+                    running: running.clone(),
+                    events: event_sender.clone(),
+                    cache_lock: cache_lock.clone(),
+                    started_at,
+                    working_copies: HashMap::new(),
+                };
+                let jobs = job_receiver.clone();
+                thread::spawn(move || worker.run(jobs))
+            })
+            .collect();
+        WorkerPool {
+            jobs: Some(job_sender),
+            workers,
+            events: event_receiver,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn submit(&self, job: Job) -> Result<(), Error> {
+        self.jobs.as_ref()
+            .ok_or_else(|| format_err!("Worker pool has been shut down"))?
+            .send(job)
+            .map_err(|_| format_err!("All workers have stopped"))
+    }
+
+    pub fn try_recv(&self) -> Option<Event> {
+        self.events.try_recv().ok()
+    }
+
+    /// Waits for the workers to finish or abandon their current builds.
+    pub fn shutdown(mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap_or(());
+        }
+    }
+}
+
+struct Worker {
+    index: usize,
+    github: Arc<GitHubClient>,
+    settings: Arc<WorkerSettings>,
+    running: Arc<AtomicBool>,
+    events: Sender<Event>,
+    /// Serialises fetches into the object caches shared between workers.
+    cache_lock: Arc<Mutex<()>>,
+    /// When this agent started, to tell its own claims apart from those it left behind last time.
+    started_at: SystemTime,
+    working_copies: HashMap<usize, LocalRepo>,
+}
+
+impl Worker {
+    fn run(mut self, jobs: Arc<Mutex<Receiver<Job>>>) {
+        loop {
+            let job = match jobs.lock() {
+                Ok(jobs) => jobs.recv(),
+                Err(_) => return,
+            };
+            let job = match job {
+                Ok(job) => job,
+                Err(_) => return,
+            };
+            let result = self.test_commit(&job);
+            let event = Event::Done {
+                target_index: job.target_index,
+                sha: job.commit.sha.to_string(),
+                result,
+            };
+            if self.events.send(event).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn send_status(&self, job: &Job, status: ui::Status) {
+        self.events.send(Event::Status {
+            target_index: job.target_index,
+            sha: job.commit.sha.to_string(),
+            status,
+        }).unwrap_or(());
+    }
+
+    fn send_output(&self, job: &Job, build: &Build) {
+        self.events.send(Event::Output {
+            target_index: job.target_index,
+            sha: job.commit.sha.to_string(),
+            lines: build.tail(),
+        }).unwrap_or(());
+    }
+
+    fn send_error(&self, error: Error) {
+        self.events.send(Event::Error { error }).unwrap_or(());
+    }
+
+    fn send_record(&self, job: &Job, started_at: SystemTime, state: State, exit_code: Option<i32>,
+                   log_keys: Vec<String>) {
+        self.events.send(Event::Finished {
+            record: BuildRecord {
+                target: job.target.label.to_string(),
+                context: job.target.context.to_string(),
+                sha: job.commit.sha.to_string(),
+                pull_request: job.commit.pull_request.clone(),
+                agent_id: self.settings.agent_id.to_string(),
+                started_at: history::timestamp(started_at),
+                finished_at: history::timestamp(SystemTime::now()),
+                state,
+                exit_code,
+                log_keys,
+            },
+        }).unwrap_or(());
+    }
+
+    fn git_credentials(&self) -> Result<GitCredentials, Error> {
+        if self.settings.git_url.starts_with("ssh://") {
+            Ok(GitCredentials::Ssh { key_file: self.settings.ssh_key_file.clone() })
+        } else {
+            let (user, token) = self.github.git_credentials()?;
+            Ok(GitCredentials::Token { user, token })
+        }
+    }
+
+    fn working_copy(&mut self, target_index: usize, target: &Target) -> Result<&mut LocalRepo, Error> {
+        if !self.working_copies.contains_key(&target_index) {
+            let local = LocalRepo::new(&self.settings.workspace, &self.settings.git_url, &target.repo,
+                                       &target.branch, &target.context, self.index,
+                                       self.settings.clean.clone(), self.settings.fetch_depth)?;
+            self.working_copies.insert(target_index, local);
+        }
+        Ok(self.working_copies.get_mut(&target_index).unwrap())
+    }
+
+    /// The result already reported for a commit, if it has been built before.
+    fn existing_result(&self, commit: &CommitLocator, context: &str) -> Result<Option<ui::Status>, Error> {
+        let now = SystemTime::now();
+        let mut orphaned_check_runs = vec![];
+        let claim = if self.settings.checks {
+            let check_runs = self.github.get_check_runs(commit, context)?;
+            orphaned_check_runs = self.orphaned_check_runs(&check_runs, now);
+            claim::from_check_runs(check_runs, now, self.settings.orphan_age)
+        } else {
+            let statuses = self.github.get_statuses_since_result(commit, context)?;
+            claim::from_statuses(statuses, now, self.settings.orphan_age)
+        };
+        Ok(match claim {
+            Claim::Finished(state) => Some(match state {
+                State::Pending => ui::Status::Pending,
+                State::Error | State::Failure => ui::Status::Failed,
+                State::Success => ui::Status::Succeeded,
+            }),
+            // Jobs are never given to two workers at once, so a claim of our own from before this
+            // agent started must have been left behind when it last stopped. Failed builds are
+            // given a result, so a later one is only left if that couldn't be reported, and is
+            // waited out like anyone else's.
+            Claim::Held(ref agent_id, last_seen) if *agent_id == self.settings.agent_id
+                && last_seen < self.started_at => self.recover_orphan(commit, context, &orphaned_check_runs)?,
+            Claim::Orphaned => self.recover_orphan(commit, context, &orphaned_check_runs)?,
+            Claim::Held(..) => Some(ui::Status::Pending),
+            Claim::Unclaimed => None,
+        })
+    }
+
+    /// The IDs of the check runs on a commit that are still open but were left behind by agents
+    /// that stopped, this one included.
+    fn orphaned_check_runs(&self, check_runs: &[CheckRun], now: SystemTime) -> Vec<u64> {
+        check_runs.iter()
+            .filter(|check_run| check_run.status != "completed")
+            .filter(|check_run| {
+                let started_at = check_run.started_at.as_ref()
+                    .map_or(UNIX_EPOCH, |started_at| claim::parse_time(started_at));
+                let is_own = check_run.external_id.as_ref() == Some(&self.settings.agent_id);
+                (is_own && started_at < self.started_at)
+                    || now.duration_since(started_at).map_or(false, |age| age >= self.settings.orphan_age)
+            })
+            .map(|check_run| check_run.id)
+            .collect()
+    }
+
+    /// Deals with a build left pending by an agent that stopped partway through, returning the
+    /// result if it isn't to be built again. In checks mode, the orphaned check runs are completed
+    /// so they don't stay in progress for good.
+    fn recover_orphan(&self, commit: &CommitLocator, context: &str,
+                      check_runs: &[u64]) -> Result<Option<ui::Status>, Error> {
+        let description = "Abandoned when the agent building it stopped";
+        let conclusion = match self.settings.orphans {
+            OrphanPolicy::Rebuild => claim::SUPERSEDED_CONCLUSION,
+            OrphanPolicy::MarkError => "failure",
+        };
+        for &check_run in check_runs {
+            self.github.update_check_run(commit, check_run, UpdateCheckRunRequest {
+                status: None,
+                conclusion: Some(conclusion),
+                details_url: None,
+                output: Some(checks::output("Abandoned", description, &[], vec![])),
+            })?;
+        }
+        match self.settings.orphans {
+            OrphanPolicy::Rebuild => Ok(None),
+            OrphanPolicy::MarkError => {
+                if !self.settings.checks {
+                    self.github.set_status(commit, SetStatusRequest {
+                        state: State::Error,
+                        target_url: None,
+                        description: Some(description),
+                        context: Some(context),
+                    })?;
+                }
+                Ok(Some(ui::Status::Failed))
+            }
+        }
+    }
+
+    /// Checks that, of all the agents that claimed a commit at once, this one claimed it first. A
+    /// check run that lost is superseded, so that only the winner's counts.
+    fn won_claim(&self, commit: &CommitLocator, context: &str, check_run: Option<u64>) -> Result<bool, Error> {
+        let now = SystemTime::now();
+        let claim = match check_run {
+            Some(_) => claim::from_check_runs(self.github.get_check_runs(commit, context)?, now,
+                                              self.settings.orphan_age),
+            None => claim::from_statuses(self.github.get_statuses_since_result(commit, context)?, now,
+                                         self.settings.orphan_age),
+        };
+        let won = match claim {
+            Claim::Held(agent_id, _) => agent_id == self.settings.agent_id,
+            Claim::Finished(_) | Claim::Orphaned | Claim::Unclaimed => false,
+        };
+        if let (false, Some(check_run)) = (won, check_run) {
+            self.github.update_check_run(commit, check_run, UpdateCheckRunRequest {
+                status: None,
+                conclusion: Some(claim::SUPERSEDED_CONCLUSION),
+                details_url: None,
+                output: Some(checks::output("Superseded", "Built by another agent", &[], vec![])),
+            })?;
+        }
+        Ok(won)
+    }
+
+    /// Posts the pending status that claims a build for this agent, or keeps that claim alive.
+    fn post_claim(&self, commit: &CommitLocator, context: &str) -> Result<(), Error> {
+        let marker = claim::marker(&self.settings.agent_id);
+        let description = format!("{} {}",
+                                  truncate_to(&format!("Building on {}", &self.settings.label),
+                                              MAX_DESCRIPTION_LENGTH - marker.chars().count() - 1),
+                                  marker);
+        self.github.set_status(commit, SetStatusRequest {
+            state: State::Pending,
+            target_url: None,
+            description: Some(&description),
+            context: Some(context),
+        })?;
+        Ok(())
+    }
+
+    /// Marks a commit as being built, returning the check run that will track it in checks mode.
+    fn report_pending(&self, commit: &CommitLocator, context: &str) -> Result<Option<u64>, Error> {
+        if self.settings.checks {
+            let check_run = self.github.create_check_run(commit, CreateCheckRunRequest {
+                name: context,
+                head_sha: &commit.sha,
+                external_id: Some(&self.settings.agent_id),
+                status: "queued",
+                details_url: None,
+                output: None,
+            })?;
+            Ok(Some(check_run.id))
+        } else {
+            self.post_claim(commit, context)?;
+            Ok(None)
+        }
+    }
+
+    fn report_progress(&self, commit: &CommitLocator, check_run: u64, build_url: &str, started: Instant,
+                       build: &Build) -> Result<(), Error> {
+        let summary = format!("Running for {} on {}", format_duration(started.elapsed()), &self.settings.label);
+        self.github.update_check_run(commit, check_run, UpdateCheckRunRequest {
+            status: Some("in_progress"),
+            conclusion: None,
+            details_url: Some(build_url),
+            output: Some(checks::output("Building", &summary, &build.tail(), vec![])),
+        })?;
+        Ok(())
+    }
+
+    /// Reports a build that couldn't be run, such as a pull request that doesn't merge cleanly into
+    /// its base.
+    fn report_error(&self, commit: &CommitLocator, context: &str, check_run: Option<u64>, title: &str,
+                    description: &str) -> Result<(), Error> {
+        let description = truncate(description);
+        self.report_patiently(|| match check_run {
+            Some(check_run) => self.github.update_check_run(commit, check_run, UpdateCheckRunRequest {
+                status: None,
+                conclusion: Some("failure"),
+                details_url: None,
+                output: Some(checks::output(title, &description, &[], vec![])),
+            }).map(|_| ()),
+            None => self.github.set_status(commit, SetStatusRequest {
+                state: State::Error,
+                target_url: None,
+                description: Some(&description),
+                context: Some(context),
+            }),
+        })
+    }
+
+    fn test_commit(&mut self, job: &Job) -> Result<(), Error> {
+        let target = &job.target;
+        let commit = &job.commit;
+        let context = &target.context;
+        if !job.force {
+            if let Some(ui_status) = self.existing_result(commit, context)? {
+                self.send_status(job, ui_status);
+                return Ok(());
+            }
+        }
+
+        self.send_status(job, ui::Status::Pending);
+        let check_run = self.report_pending(commit, context)?;
+        if !self.won_claim(commit, context, check_run)? {
+            return Ok(());
+        }
+        let result = self.build_claimed(job, check_run);
+        // Left pending, the claim would be taken for an orphan and the build tried again at once.
+        // Builds abandoned on shutdown are left for recovery, though.
+        if let Err(e) = &result {
+            if self.running.load(Ordering::SeqCst) {
+                self.send_status(job, ui::Status::Failed);
+                self.report_error(commit, context, check_run, "Error", &format!("Error: {}", e))
+                    .unwrap_or_else(|e| self.send_error(e));
+            }
+        }
+        result
+    }
+
+    /// Builds a commit once this agent has claimed it, and reports the result.
+    fn build_claimed(&mut self, job: &Job, check_run: Option<u64>) -> Result<(), Error> {
+        let target = &job.target;
+        let commit = &job.commit;
+        let context = &target.context;
+        let claimed_at = SystemTime::now();
+        let credentials = self.git_credentials()?;
+        let cache_lock = self.cache_lock.clone();
+        let local = self.working_copy(job.target_index, target)?;
+        if let Err(e) = local.reset_to(commit, &credentials, &cache_lock) {
+            return match e.downcast::<LocalRepoError>() {
+                Ok(e) => {
+                    let title = match e {
+                        LocalRepoError::MergeConflict { .. } => "Merge conflict",
+                        LocalRepoError::HistoryTooShallow { .. } => "Not enough history",
+                    };
+                    self.send_status(job, ui::Status::Failed);
+                    self.send_record(job, claimed_at, State::Error, None, vec![]);
+                    self.report_error(commit, context, check_run, title, &e.to_string())
+                }
+                Err(e) => Err(e),
+            };
+        }
+        let checkout = local.path().to_string();
+        let path_to_script = format!("{}/{}", &checkout, &target.script);
+        build::roll_logs(&target.log_root)?;
+        let mut build = Build::start(&path_to_script, &target.log_root.join(&commit.sha))?;
+        self.events.send(Event::Started {
+            target_index: job.target_index,
+            sha: commit.sha.to_string(),
+        }).unwrap_or(());
+        let started = Instant::now();
+        let started_at = SystemTime::now();
+        // Presigned index pages only list the logs uploaded so far, so this is for use while the
+        // build runs, and is replaced once the complete logs are up.
+        let progress_url = target.get_url(&commit.sha)?;
+        let deadline = self.settings.timeout.map(|timeout| started + timeout);
+        if let Some(check_run) = check_run {
+            self.report_progress(commit, check_run, &progress_url, started, &build)?;
+        }
+        let mut next_upload = Instant::now() + UPLOAD_PERIOD;
+        let mut next_heartbeat = Instant::now() + claim::HEARTBEAT_PERIOD;
+        let exit_status = loop {
+            if let Some(exit_status) = build.try_wait()? {
+                break Some(exit_status);
+            }
+            if !self.running.load(Ordering::SeqCst) {
+                build.kill()?;
+                return Err(format_err!("Build of {} was abandoned on shutdown", &commit.sha));
+            }
+            if job.cancelled.load(Ordering::SeqCst) || deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                build.kill()?;
+                break None;
+            }
+            // A failed upload or update is tried again next time round, rather than abandoning the build.
+            if Instant::now() >= next_upload {
+                upload_chunks(target.store.as_ref(), commit, &mut build)
+                    .unwrap_or_else(|e| self.send_error(e));
+                if let Some(check_run) = check_run {
+                    self.report_progress(commit, check_run, &progress_url, started, &build)
+                        .unwrap_or_else(|e| self.send_error(e));
+                }
+                next_upload = Instant::now() + UPLOAD_PERIOD;
+            }
+            if check_run.is_none() && Instant::now() >= next_heartbeat {
+                self.post_claim(commit, context)
+                    .unwrap_or_else(|e| self.send_error(e));
+                next_heartbeat = Instant::now() + claim::HEARTBEAT_PERIOD;
+            }
+            self.send_output(job, &build);
+            thread::sleep(POLL_PERIOD);
+        };
+        self.send_output(job, &build);
+        let duration = started.elapsed();
+        let new_state = match exit_status {
+            Some(exit_status) if exit_status.success() => {
+                self.send_status(job, ui::Status::Succeeded);
+                State::Success
+            }
+            Some(_) => {
+                self.send_status(job, ui::Status::Failed);
+                State::Failure
+            }
+            None => {
+                self.send_status(job, ui::Status::Failed);
+                State::Error
+            }
+        };
+        // The result is reported whether or not the logs could be stored, as the commit would
+        // otherwise be left pending.
+        let stored = store_logs(target, commit, &mut build)
+            .and_then(|log_keys| Ok((log_keys, target.get_url(&commit.sha)?)));
+        let (log_keys, build_url) = match stored {
+            Ok((log_keys, build_url)) => (log_keys, Some(build_url)),
+            Err(e) => {
+                self.send_error(e);
+                (vec![], None)
+            }
+        };
+        let build_url = build_url.as_ref().map(String::as_str);
+        let exit_code = exit_status.and_then(|exit_status| exit_status.code());
+        self.send_record(job, started_at, new_state, exit_code, log_keys);
+        let cancelled = job.cancelled.load(Ordering::SeqCst) && exit_status.is_none();
+        let description = if cancelled {
+            format!("Cancelled after {}", format_duration(duration))
+        } else {
+            truncate(&build.log_lines().ok().and_then(custom_description)
+                .unwrap_or_else(|| describe_outcome(exit_status, duration)))
+        };
+        let (conclusion, title) = match new_state {
+            State::Success => ("success", "Succeeded"),
+            State::Failure => ("failure", "Failed"),
+            State::Error | State::Pending if cancelled => ("cancelled", "Cancelled"),
+            State::Error | State::Pending => ("timed_out", "Timed out"),
+        };
+        let summary = format!("{} on {}", &description, &self.settings.label);
+        let tail = build.tail();
+        // The logs are read back from disk rather than kept in memory, as they can be any length.
+        let annotations = match (check_run, build.log_lines()) {
+            (Some(_), Ok(lines)) => checks::annotations(lines, &checkout),
+            _ => vec![],
+        };
+        self.report_patiently(|| match check_run {
+            Some(check_run) => self.github.update_check_run(commit, check_run, UpdateCheckRunRequest {
+                status: None,
+                conclusion: Some(conclusion),
+                details_url: build_url,
+                output: Some(checks::output(title, &summary, &tail, annotations.clone())),
+            }).map(|_| ()),
+            None => self.github.set_status(commit, SetStatusRequest {
+                state: new_state,
+                target_url: build_url,
+                description: Some(&description),
+                context: Some(context),
+            }),
+        })
+    }
+
+    /// Reports the outcome of a build. If the API quota has run out, this waits for it to be
+    /// restored rather than losing the outcome, which would leave the commit pending.
+    fn report_patiently<F>(&self, report: F) -> Result<(), Error> where F: Fn() -> Result<(), GitHubError> {
+        loop {
+            let seconds = match report() {
+                Err(GitHubError::RateLimited { seconds }) => seconds,
+                result => return Ok(result?),
+            };
+            let resume = Instant::now() + Duration::from_secs(seconds);
+            while Instant::now() < resume {
+                if !self.running.load(Ordering::SeqCst) {
+                    return Err(format_err!("Gave up waiting for the API quota on shutdown"));
+                }
+                thread::sleep(RATE_LIMIT_POLL_PERIOD);
+            }
+        }
+    }
+}
+
+/// Uploads any output written since the last upload, so logs are visible while a build runs.
+fn upload_chunks(store: &dyn LogStore, commit: &CommitLocator, build: &mut Build) -> Result<(), Error> {
+    for log in build.logs().iter_mut() {
+        if let Some((index, chunk)) = log.next_chunk()? {
+            let length = chunk.len();
+            store.put(&format!("{}/{}/{:05}.txt", commit.sha, log.name, index), chunk)?;
+            log.mark_uploaded(length);
+        }
+    }
+    Ok(())
+}
+
+/// Uploads what is left of a build's output, followed by its complete logs, returning the keys of
+/// the complete logs.
+fn store_logs(target: &Target, commit: &CommitLocator, build: &mut Build) -> Result<Vec<String>, Error> {
+    upload_chunks(target.store.as_ref(), commit, build)?;
+    let mut log_keys = vec![];
+    for log_file in build.logs().iter() {
+        let log_key = format!("{}/{}.txt", commit.sha, log_file.name);
+        target.store.put(&log_key, log_file.contents()?)?;
+        log_keys.push(log_key);
+    }
+    Ok(log_keys)
+}
+
+/// The description chosen by the build script with its last `::crane-description::` line.
+fn custom_description<I>(lines: I) -> Option<String> where I: Iterator<Item = String> {
+    lines.filter(|line| line.starts_with(DESCRIPTION_MARKER))
+        .map(|line| line[DESCRIPTION_MARKER.len()..].trim().to_string())
+        .filter(|description| !description.is_empty())
+        .last()
+}
+
+/// Describes how a build ended, e.g. "Failed in 2m 5s with exit code 1". A build without an exit
+/// status was killed for running too long.
+fn describe_outcome(exit_status: Option<ExitStatus>, duration: Duration) -> String {
+    let duration = format_duration(duration);
+    match exit_status {
+        Some(exit_status) if exit_status.success() => format!("Succeeded in {}", duration),
+        Some(exit_status) => match (exit_status.code(), exit_status.signal()) {
+            (Some(code), _) => format!("Failed in {} with exit code {}", duration, code),
+            (None, Some(signal)) => format!("Failed in {}, killed by signal {}", duration, signal),
+            (None, None) => format!("Failed in {}", duration),
+        },
+        None => format!("Timed out after {}", duration),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, s) => format!("{}h {}m {}s", h, m, s),
+    }
+}
+
+fn truncate(description: &str) -> String {
+    truncate_to(description, MAX_DESCRIPTION_LENGTH)
+}
+
+fn truncate_to(description: &str, max_length: usize) -> String {
+    if description.chars().count() <= max_length {
+        description.to_string()
+    } else {
+        let mut truncated: String = description.chars().take(max_length - 1).collect();
+        truncated.push('…');
+        truncated
+    }
+}